@@ -1,5 +1,9 @@
 use mess::messflow::run;
+use std::process::exit;
 
 fn main() {
-    run();
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        exit(e.exit_code());
+    }
 }
@@ -0,0 +1,131 @@
+//! Lints for deps files, as run by `messctl check`.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use crate::*;
+use crate::config::Config;
+use crate::diagnostics::Diagnostic;
+use crate::version::Requirement;
+
+/// Reads and lints some deps files of a project, with the sources the
+/// config says they are for. Files that do not exist are skipped.
+pub fn check_files(files: &[PathBuf], config: &Config) -> Result<Vec<Diagnostic>, Error> {
+    let mut documents = Vec::new();
+    for path in files {
+        if let Some(text) = read_file(path)? {
+            documents.push((path.clone(), config.source_of(path), parse_text(&text)));
+        }
+    }
+    Ok(check_documents(&documents, &config.root))
+}
+
+/// Lints parsed deps files of the project in `root`, each with its
+/// source if known, both one by one and against each other.
+pub fn check_documents(files: &[(PathBuf, Option<Source>, Document)], root: &Path) -> Vec<Diagnostic> {
+    let mut found = Vec::new();
+    for (path, source, document) in files {
+        check_document(path, *source, document, root, &mut found);
+    }
+    check_sources(files, &mut found);
+    found.sort_by(|a, b| (&a.path, a.line, a.column).cmp(&(&b.path, b.line, b.column)));
+    found
+}
+
+fn diagnostic(path: &Path, line: &Line, column: usize, message: &str) -> Diagnostic {
+    Diagnostic::new(path, line.number(), column, message, &line.to_string())
+}
+
+fn check_document(path: &Path, source: Option<Source>, document: &Document, root: &Path, found: &mut Vec<Diagnostic>) {
+    let mut seen: Vec<&str> = Vec::new();
+    for line in document.iter() {
+        if let Line::Malformed(m) = line {
+            found.push(Diagnostic::new(path, m.number, m.column, &m.message, &m.text));
+        }
+        let (package, spans) = match (line.package(), line.spans()) {
+            (Some(package), Some(spans)) => (package, spans),
+            _ => continue,
+        };
+        if !seen.contains(&package.name.as_str()) {
+            seen.push(&package.name);
+            let refs = get_refs(document, &package.name);
+            for duplicate in refs.iter().skip(1).map(|i| &document[*i]) {
+                let message = format!("duplicate entry for {}, first seen on line {}", package.name, line.number());
+                found.push(diagnostic(path, duplicate, duplicate.spans().unwrap().name.start, &message));
+            }
+        }
+        let enabled = matches!(line, Line::Enabled(_));
+        if let Some(message) = source.and_then(|s| check_value(s, &package.version, enabled, root)) {
+            found.push(diagnostic(path, line, spans.value.start, &message));
+        }
+    }
+}
+
+/// Whether a value looks like it belongs in `deps.git`.
+fn looks_like_git(value: &str) -> bool {
+    value.contains("://") || value.starts_with("git@") || value.split('#').next().unwrap().ends_with(".git")
+}
+
+/// Problems with a value given the deps file it is in. Paths are
+/// relative to the project in `root`.
+fn check_value(source: Source, value: &str, enabled: bool, root: &Path) -> Option<String> {
+    match source {
+        Source::Hex => match value.parse::<Requirement>() {
+            Ok(_) => None,
+            Err(_) if looks_like_git(value) => Some("git spec in deps.hex".to_string()),
+            Err(e) => Some(format!("invalid hex requirement: {}", e)),
+        },
+        Source::Git if value.parse::<Requirement>().is_ok() => Some("hex requirement in deps.git".to_string()),
+        Source::Git => None,
+        Source::Path if enabled && !root.join(value).is_dir() => {
+            Some(format!("directory {} does not exist", value))
+        }
+        Source::Path => None,
+    }
+}
+
+/// Flags packages enabled in more than one source. Only the one with
+/// the highest precedence is used, so the others are misleading.
+fn check_sources(files: &[(PathBuf, Option<Source>, Document)], found: &mut Vec<Diagnostic>) {
+    let mut enabled: BTreeMap<&str, Vec<(Source, &Path, &Line)>> = BTreeMap::new();
+    for (path, source, document) in files {
+        let source = match *source {
+            Some(source) => source,
+            None => continue,
+        };
+        for line in document.iter() {
+            if let Line::Enabled(e) = line {
+                enabled.entry(&e.package.name).or_default().push((source, path, line));
+            }
+        }
+    }
+    for (package, mut entries) in enabled {
+        entries.sort_by_key(|(source, _, line)| (std::cmp::Reverse(*source), line.number()));
+        let (winner, winner_path, winner_line) = entries[0];
+        for (_, path, line) in entries.iter().filter(|(source, _, _)| *source != winner) {
+            let message = format!(
+                "{} is also enabled in {}:{}, which takes precedence",
+                package, winner_path.display(), winner_line.number(),
+            );
+            found.push(diagnostic(path, line, line.spans().unwrap().name.start, &message));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_text(file: &str, text: &str) -> Vec<Diagnostic> {
+        let source = Source::of_file(Path::new(file));
+        check_documents(&[(PathBuf::from(file), source, parse_text(text))], Path::new("."))
+    }
+
+    #[test]
+    fn duplicate_points_at_its_own_name() {
+        let found = check_text("deps.hex", "beta = \"1.0.0\"\n  # beta = \"2.0.0\"\n");
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].line, found[0].column), (2, 4));
+        assert_eq!(found[0].message, "duplicate entry for beta, first seen on line 1");
+        assert_eq!(&found[0].text[found[0].column..], "beta = \"2.0.0\"");
+    }
+}
@@ -0,0 +1,288 @@
+//! Project settings, read from `.messctl.toml` with `MESS_*`
+//! environment variables taking precedence.
+//!
+//! ```toml
+//! forks = "forks"
+//! flavours = "flavours"
+//! comment_marker = "# "
+//! return_source = "git"
+//!
+//! [files]
+//! hex = "config/deps.hex"
+//! git = "config/deps.git"
+//! path = "config/deps.path"
+//!
+//! [defaults]
+//! add = ["config/deps.hex"]
+//! disable = ["config/deps.hex", "config/deps.git"]
+//! ```
+
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use crate::*;
+use crate::diagnostics::{did_you_mean, Diagnostic};
+
+/// The name of the config file.
+pub const CONFIG: &str = ".messctl.toml";
+
+/// How a disabled dependency is commented out unless configured
+/// otherwise.
+pub const DEFAULT_MARKER: &str = "# ";
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The project directory, which the paths below are relative to.
+    #[serde(skip)]
+    pub root: PathBuf,
+    /// Where each deps file is.
+    pub files: Files,
+    /// Directory `messflow fork` clones into.
+    pub forks: PathBuf,
+    /// Directory holding a directory per flavour, each with its own
+    /// copy of the deps files.
+    pub flavours: PathBuf,
+    /// Prefix used when disabling a dependency, e.g. `"# "` or `"#"`.
+    pub comment_marker: String,
+    /// Where `messflow return` goes back to without `--hex` or `--git`.
+    pub return_source: Source,
+    /// Files each messctl command works on when none are given.
+    pub defaults: Defaults,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Files {
+    pub hex: PathBuf,
+    pub git: PathBuf,
+    pub path: PathBuf,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Defaults {
+    pub add: Vec<PathBuf>,
+    pub update: Vec<PathBuf>,
+    pub delete: Vec<PathBuf>,
+    pub enable: Vec<PathBuf>,
+    pub disable: Vec<PathBuf>,
+    pub list: Vec<PathBuf>,
+    pub check: Vec<PathBuf>,
+    pub fmt: Vec<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            root: PathBuf::from("."),
+            files: Files::default(),
+            forks: PathBuf::from("forks"),
+            flavours: PathBuf::from("flavours"),
+            comment_marker: DEFAULT_MARKER.to_string(),
+            return_source: Source::Git,
+            defaults: Defaults::default(),
+        }
+    }
+}
+
+impl Default for Files {
+    fn default() -> Files {
+        let file = |source: Source| PathBuf::from(source.file_name());
+        Files { hex: file(Source::Hex), git: file(Source::Git), path: file(Source::Path) }
+    }
+}
+
+impl Config {
+    /// Loads the config for the project in `dir`, or if not given, the
+    /// one the current directory is in.
+    pub fn discover(dir: Option<&Path>) -> Result<Config, Error> {
+        match dir {
+            Some(dir) if !dir.is_dir() => Err(Error::NotADirectory(dir.to_owned())),
+            Some(dir) => Config::load(dir),
+            None => Config::load(&find_root()?),
+        }
+    }
+
+    /// Reads `.messctl.toml` from a project directory, if there is one,
+    /// then applies any `MESS_*` environment variables.
+    pub fn load(root: &Path) -> Result<Config, Error> {
+        let path = under(root, Path::new(CONFIG));
+        let mut config: Config = match read_file(&path)? {
+            Some(text) => toml::from_str(&text).map_err(|e| toml_error(&path, &text, e))?,
+            None => Config::default(),
+        };
+        config.apply_env()?;
+        check_marker(&config.comment_marker)?;
+        config.root = root.to_owned();
+        let files = &mut config.files;
+        for file in [&mut files.hex, &mut files.git, &mut files.path] {
+            *file = under(root, file);
+        }
+        let defaults = &mut config.defaults;
+        for files in [
+            &mut defaults.add, &mut defaults.update, &mut defaults.delete, &mut defaults.enable,
+            &mut defaults.disable, &mut defaults.list, &mut defaults.check, &mut defaults.fmt,
+        ] {
+            for file in files.iter_mut() {
+                *file = under(root, file);
+            }
+        }
+        Ok(config)
+    }
+
+    fn apply_env(&mut self) -> Result<(), Error> {
+        let var = |name: &str| env::var_os(name).filter(|v| !v.is_empty());
+        for (name, file) in [
+            ("MESS_HEX", &mut self.files.hex),
+            ("MESS_GIT", &mut self.files.git),
+            ("MESS_PATH", &mut self.files.path),
+            ("MESS_FORKS", &mut self.forks),
+            ("MESS_FLAVOURS", &mut self.flavours),
+        ] {
+            if let Some(value) = var(name) {
+                *file = PathBuf::from(value);
+            }
+        }
+        if let Some(marker) = var("MESS_COMMENT_MARKER") {
+            self.comment_marker = marker.to_string_lossy().into_owned();
+        }
+        if let Ok(source) = env::var("MESS_RETURN_SOURCE") {
+            self.return_source = source.parse()
+                .map_err(|reason| Error::InvalidValue { value: source, reason })?;
+        }
+        let defaults = &mut self.defaults;
+        for (name, files) in [
+            ("MESS_ADD_FILES", &mut defaults.add),
+            ("MESS_UPDATE_FILES", &mut defaults.update),
+            ("MESS_DELETE_FILES", &mut defaults.delete),
+            ("MESS_ENABLE_FILES", &mut defaults.enable),
+            ("MESS_DISABLE_FILES", &mut defaults.disable),
+            ("MESS_LIST_FILES", &mut defaults.list),
+            ("MESS_CHECK_FILES", &mut defaults.check),
+            ("MESS_FMT_FILES", &mut defaults.fmt),
+        ] {
+            if let Some(value) = var(name) {
+                *files = env::split_paths(&value).collect();
+            }
+        }
+        Ok(())
+    }
+
+    /// Where the journal of changes is kept.
+    pub fn journal(&self) -> PathBuf {
+        under(&self.root, Path::new(journal::JOURNAL))
+    }
+
+    /// Where the deps file for a source is.
+    pub fn file(&self, source: Source) -> &Path {
+        match source {
+            Source::Hex => &self.files.hex,
+            Source::Git => &self.files.git,
+            Source::Path => &self.files.path,
+        }
+    }
+
+    /// The source whose deps file `path` is: the one it is configured
+    /// as, or a flavour's copy of, or failing that, judging by its name.
+    pub fn source_of(&self, path: &Path) -> Option<Source> {
+        let configured = |s: &Source| {
+            let file = self.file(*s);
+            path == file || path.ends_with(file.strip_prefix(&self.root).unwrap_or(file))
+        };
+        Source::ALL.iter().copied().find(configured).or_else(|| Source::of_file(path))
+    }
+
+    /// The deps files for every source, lowest precedence first.
+    pub fn all_files(&self) -> Vec<PathBuf> {
+        Source::ALL.iter().map(|s| self.file(*s).to_owned()).collect()
+    }
+
+    /// The names of the flavours, sorted. A project without a flavours
+    /// directory has none.
+    pub fn flavours(&self) -> Result<Vec<String>, Error> {
+        let dir = under(&self.root, &self.flavours);
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(Error::io(&dir, e)),
+        };
+        let mut names = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(|e| Error::io(&dir, e))?;
+            if entry.path().is_dir() {
+                names.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Checks a flavour exists.
+    pub fn check_flavour(&self, name: &str) -> Result<(), Error> {
+        let flavours = self.flavours()?;
+        if flavours.iter().any(|f| f == name) {
+            return Ok(());
+        }
+        let suggestion = did_you_mean(name, flavours.iter().map(|f| f.as_str()));
+        Err(Error::UnknownFlavour { name: name.to_owned(), suggestion })
+    }
+
+    /// A flavour's copy of a deps file. `file` is relative to the project,
+    /// or under `root` as the paths in the config are.
+    pub fn flavour_file(&self, flavour: &str, file: &Path) -> PathBuf {
+        let file = file.strip_prefix(&self.root).unwrap_or(file);
+        under(&self.root, &self.flavours.join(flavour).join(file))
+    }
+}
+
+/// A path relative to the project as seen from the current directory.
+pub fn under(root: &Path, path: &Path) -> PathBuf {
+    // keep paths in messages short when working in the project itself
+    if root == Path::new(".") { path.to_owned() } else { root.join(path) }
+}
+
+/// Walks up from the current directory to the nearest one that looks
+/// like a mess project: it has a `.messctl.toml`, `mess.exs` or deps
+/// files. Returns it relative to the current directory, e.g. `../..`,
+/// or `.` if there is none.
+pub fn find_root() -> Result<PathBuf, Error> {
+    let cwd = env::current_dir().map_err(|e| Error::io(Path::new("."), e))?;
+    let markers = [CONFIG, "mess.exs", "deps.hex", "deps.git", "deps.path"];
+    for (up, dir) in cwd.ancestors().enumerate() {
+        if markers.iter().any(|m| dir.join(m).exists()) {
+            return Ok(match up {
+                0 => PathBuf::from("."),
+                _ => (0..up).map(|_| "..").collect(),
+            });
+        }
+    }
+    Ok(PathBuf::from("."))
+}
+
+/// A marker must read back as a disabled dependency: some `#`s,
+/// optionally followed by spaces.
+fn check_marker(marker: &str) -> Result<(), Error> {
+    let hashes = marker.trim_end_matches(' ');
+    if hashes.is_empty() || !hashes.chars().all(|c| c == '#') {
+        return Err(Error::InvalidValue {
+            value: marker.to_string(),
+            reason: "a comment marker must be one or more `#` followed by any spaces".to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn toml_error(path: &Path, text: &str, error: toml::de::Error) -> Error {
+    let (line, column) = error.line_col().unwrap_or((0, 0));
+    let source_line = text.lines().nth(line).unwrap_or("");
+    // toml counts characters, diagnostics count bytes
+    let column = source_line.char_indices().nth(column).map(|(i, _)| i).unwrap_or(source_line.len());
+    let mut message = error.to_string();
+    if let Some(at) = message.find(" at line ") {
+        message.truncate(at);
+    }
+    Error::Parse(Diagnostic::new(path, line + 1, column, &message, source_line))
+}
@@ -0,0 +1,58 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// A problem at a particular place in a deps file, reported
+/// compiler-style with the offending line and a caret under it.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub path: PathBuf,
+    /// Starting at 1.
+    pub line: usize,
+    /// Byte offset into `text`.
+    pub column: usize,
+    pub message: String,
+    /// The text of the offending line.
+    pub text: String,
+}
+
+impl Diagnostic {
+    pub fn new(path: &Path, line: usize, column: usize, message: &str, text: &str) -> Diagnostic {
+        Diagnostic {
+            path: path.to_owned(), line, column,
+            message: message.to_owned(), text: text.to_owned(),
+        }
+    }
+
+    /// The column as an editor would count it, starting at 1.
+    pub fn display_column(&self) -> usize {
+        let column = self.column.min(self.text.len());
+        self.text.get(..column).map(|t| t.chars().count()).unwrap_or(column) + 1
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.path.display(), self.line, self.display_column(), self.message)?;
+        if !self.text.is_empty() {
+            // keep tabs so the caret lines up however they are rendered
+            let pad: String = self.text.chars().take(self.display_column() - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            write!(f, "\n    {}\n    {}^", self.text, pad)?;
+        }
+        Ok(())
+    }
+}
+
+/// The candidate closest to `name`, if any is close enough to be a
+/// plausible typo.
+pub fn did_you_mean<'a, I>(name: &str, candidates: I) -> Option<String>
+where I: IntoIterator<Item = &'a str> {
+    let limit = (name.chars().count() / 3).max(1);
+    candidates.into_iter()
+        .filter(|c| *c != name)
+        .map(|c| (strsim::levenshtein(name, c), c))
+        .filter(|(distance, _)| *distance <= limit)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, c)| c.to_owned())
+}
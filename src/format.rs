@@ -0,0 +1,91 @@
+//! The canonical layout of deps files, as applied by `messctl fmt`.
+//!
+//! Dependencies are written `name = "value"` with no indentation,
+//! disabled ones are prefixed with the comment marker (`# ` unless
+//! configured otherwise), comments keep their `#`s, so `##` section
+//! headers stay headers, but lose their indentation and get one space
+//! after the marker, and blank lines are empty. Malformed lines are left
+//! alone.
+
+use crate::types::*;
+
+/// Rewrites every line of a document into canonical form, disabling
+/// dependencies with `marker`. If `sort` is set, runs of consecutive
+/// dependency lines (sections separated by comments or blank lines) are
+/// also sorted by package name.
+pub fn format_document(document: &mut Document, sort: bool, marker: &str) {
+    for line in document.iter_mut() {
+        format_line(line, marker);
+    }
+    if sort {
+        sort_sections(document);
+    }
+}
+
+fn format_package(package: &mut Package) {
+    package.eq = " = ".to_string();
+    package.quote = Quote::Double;
+}
+
+/// Trailing spaces go, a trailing comment gets one space either side
+/// of its marker.
+fn format_post(post: &str) -> String {
+    let post = post.trim();
+    match post.trim_start_matches('#').trim() {
+        _ if post.is_empty() => String::new(),
+        "" => " #".to_string(),
+        comment => format!(" # {}", comment),
+    }
+}
+
+pub fn format_line(line: &mut Line, marker: &str) {
+    match line {
+        Line::Enabled(e) => {
+            e.pre = String::new();
+            format_package(&mut e.package);
+            e.post = format_post(&e.post);
+        }
+        Line::Disabled(d) => {
+            d.pre = marker.to_string();
+            format_package(&mut d.package);
+            d.post = format_post(&d.post);
+        }
+        Line::Blank(b) => b.text = String::new(),
+        Line::Comment(c) => {
+            c.text = c.text.trim_end().to_string();
+            let hashes = c.marker.trim();
+            c.marker = if c.text.is_empty() { hashes.to_string() } else { format!("{} ", hashes) };
+        }
+        Line::Malformed(_) => {}
+    }
+}
+
+fn sort_sections(document: &mut Document) {
+    let mut start = 0;
+    while start < document.len() {
+        let len = document[start..].iter().take_while(|l| l.package().is_some()).count();
+        if len > 1 {
+            let section = &mut document[start..start + len];
+            // line endings belong to positions in the file, not to lines
+            let eols: Vec<Eol> = section.iter().map(|l| l.eol()).collect();
+            section.sort_by(|a, b| a.package().unwrap().name.cmp(&b.package().unwrap().name));
+            for (line, eol) in section.iter_mut().zip(eols) {
+                line.set_eol(eol);
+            }
+        }
+        start += len.max(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_text;
+
+    #[test]
+    fn comments_keep_their_markers() {
+        let mut document = parse_text("  ##   Section A  \n#comment\n  #\n### \n");
+        format_document(&mut document, false, "# ");
+        assert_eq!(document.to_string(), "## Section A\n# comment\n#\n###\n");
+    }
+}
@@ -0,0 +1,184 @@
+//! A record of what each command changed in the deps files, kept one
+//! JSON object per line in `.mess/journal.jsonl` so `messctl undo` can
+//! revert it.
+
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::*;
+use crate::diagnostics::Diagnostic;
+
+/// Where the journal is kept, relative to the project.
+pub const JOURNAL: &str = ".mess/journal.jsonl";
+
+/// The changes one command made.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Record {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The command line, program name first.
+    pub command: Vec<String>,
+    /// The files written, in order, with absolute paths.
+    pub changes: Vec<Change>,
+}
+
+/// Appends the changes made by the running command to a journal.
+pub fn record(journal: &Path, changes: Vec<Change>) -> Result<(), Error> {
+    let cwd = std::env::current_dir().map_err(|e| Error::io(Path::new("."), e))?;
+    let changes = changes.into_iter().map(|c| {
+        let path = std::fs::canonicalize(&c.path).unwrap_or_else(|_| cwd.join(&c.path));
+        Change { path, ..c }
+    }).collect();
+    let record = Record {
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        command: std::env::args().collect(),
+        changes,
+    };
+    if let Some(dir) = journal.parent() {
+        create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+    }
+    let line = serde_json::to_string(&record).unwrap();
+    OpenOptions::new().create(true).append(true).open(journal)
+        .and_then(|mut file| writeln!(file, "{}", line))
+        .map_err(|e| Error::io(journal, e))
+}
+
+/// Reads every record in a journal, oldest first. A journal that does
+/// not exist is empty.
+pub fn read(journal: &Path) -> Result<Vec<Record>, Error> {
+    let text = read_file(journal)?.unwrap_or_default();
+    text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()).map(|(i, line)| {
+        serde_json::from_str(line).map_err(|e| {
+            let message = format!("invalid journal entry: {}", e);
+            Error::Parse(Diagnostic::new(journal, i + 1, 0, &message, ""))
+        })
+    }).collect()
+}
+
+/// Reverts the last `count` records in a journal and removes them from
+/// it. Nothing is written unless every file is still as the records
+/// left it.
+pub fn undo(journal: &Path, count: usize, writer: &mut Writer) -> Result<(), Error> {
+    let mut records = read(journal)?;
+    if count > records.len() {
+        return Err(Error::NothingToUndo { requested: count, available: records.len() });
+    }
+    let undone = records.split_off(records.len() - count);
+    // the contents of each file on disk, and as undoing leaves them
+    let mut on_disk: BTreeMap<PathBuf, Option<String>> = BTreeMap::new();
+    let mut undoing: BTreeMap<PathBuf, Option<String>> = BTreeMap::new();
+    for record in undone.iter().rev() {
+        println!("Undoing {}", record.command.join(" "));
+        for change in record.changes.iter().rev() {
+            let current = match undoing.get(&change.path) {
+                Some(contents) => contents.clone(),
+                None => {
+                    let contents = read_file(&change.path)?;
+                    on_disk.insert(change.path.clone(), contents.clone());
+                    contents
+                }
+            };
+            if current != change.after {
+                return Err(Error::ChangedSince(change.path.clone()));
+            }
+            undoing.insert(change.path.clone(), change.before.clone());
+        }
+    }
+    let cwd = std::env::current_dir().map_err(|e| Error::io(Path::new("."), e))?;
+    for (path, after) in undoing {
+        let before = on_disk.remove(&path).unwrap();
+        if before == after {
+            continue;
+        }
+        let path = path.strip_prefix(&cwd).map(|p| p.to_owned()).unwrap_or(path);
+        if let Err(e) = writer.apply(Change { path, before, after }) {
+            return Err(writer.abort(0, e));
+        }
+    }
+    if writer.options.dry_run {
+        return Ok(());
+    }
+    let kept: String = records.iter()
+        .map(|r| serde_json::to_string(r).unwrap() + "\n")
+        .collect();
+    write_text(&kept, journal, &WriteOptions::default()).map_err(|e| Error::io(journal, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// An empty directory of its own for a test to write into.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mess-journal-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes `after` to a file and journals it as one command.
+    fn run(journal: &Path, path: &Path, before: Option<&str>, after: &str) {
+        fs::write(path, after).unwrap();
+        let change = Change { path: path.to_owned(), before: before.map(String::from), after: Some(after.to_string()) };
+        record(journal, vec![change]).unwrap();
+    }
+
+    #[test]
+    fn undoes_several_commands_on_one_file() {
+        let dir = scratch("several");
+        let (journal, file) = (dir.join(JOURNAL), dir.join("deps.hex"));
+        fs::write(&file, "a").unwrap();
+        run(&journal, &file, Some("a"), "b");
+        run(&journal, &file, Some("b"), "c");
+        run(&journal, &file, Some("c"), "d");
+        undo(&journal, 2, &mut Writer::default()).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "b");
+        assert_eq!(read(&journal).unwrap().len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn undoing_a_new_file_removes_it() {
+        let dir = scratch("created");
+        let (journal, file) = (dir.join(JOURNAL), dir.join("deps.path"));
+        run(&journal, &file, None, "foo = \"../foo\"\n");
+        undo(&journal, 1, &mut Writer::default()).unwrap();
+        assert!(!file.exists());
+        assert!(read(&journal).unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_to_undo_a_file_changed_since() {
+        let dir = scratch("changed");
+        let (journal, hex, git) = (dir.join(JOURNAL), dir.join("deps.hex"), dir.join("deps.git"));
+        run(&journal, &git, None, "foo = \"git\"\n");
+        run(&journal, &hex, None, "foo = \"1.0.0\"\n");
+        fs::write(&git, "edited by hand\n").unwrap();
+        match undo(&journal, 2, &mut Writer::default()) {
+            Err(Error::ChangedSince(path)) => assert_eq!(path, fs::canonicalize(&git).unwrap()),
+            result => panic!("unexpected {:?}", result),
+        }
+        // nothing is touched, not even the file that could be undone
+        assert_eq!(fs::read_to_string(&hex).unwrap(), "foo = \"1.0.0\"\n");
+        assert_eq!(fs::read_to_string(&git).unwrap(), "edited by hand\n");
+        assert_eq!(read(&journal).unwrap().len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cannot_undo_more_than_the_journal_has() {
+        let dir = scratch("too-many");
+        let journal = dir.join(JOURNAL);
+        run(&journal, &dir.join("deps.hex"), None, "a");
+        assert!(matches!(
+            undo(&journal, 2, &mut Writer::default()),
+            Err(Error::NothingToUndo { requested: 2, available: 1 })
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
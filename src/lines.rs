@@ -1,8 +1,125 @@
-use std::fs::File;
-use std::io::{Error, Write};
-use std::path::Path;
-use std::process::exit;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
 use crate::*;
+use serde::{Deserialize, Serialize};
+
+/// How deps files get written to disk.
+#[derive(Clone, Debug, Default)]
+pub struct WriteOptions {
+    /// Keep the previous contents of a file next to it as `<name>.orig`.
+    pub backup: bool,
+    /// Don't write anything, only record what would have been written.
+    pub dry_run: bool,
+    /// Where to record what each command changed, so it can be undone.
+    pub journal: Option<PathBuf>,
+}
+
+/// One file written, or that would have been in a dry run.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Change {
+    pub path: PathBuf,
+    /// The contents before, `None` if the file did not exist.
+    pub before: Option<String>,
+    /// The contents after, `None` if the file was removed.
+    pub after: Option<String>,
+}
+
+impl Change {
+    /// The change as a unified diff.
+    pub fn diff(&self) -> String {
+        let name = |prefix: &str, contents: &Option<String>| match contents {
+            Some(_) => format!("{}/{}", prefix, self.path.display().to_string().trim_start_matches('/')),
+            None => "/dev/null".to_string(),
+        };
+        let before = self.before.as_deref().unwrap_or("");
+        let after = self.after.as_deref().unwrap_or("");
+        similar::TextDiff::from_lines(before, after)
+            .unified_diff()
+            .header(&name("a", &self.before), &name("b", &self.after))
+            .to_string()
+    }
+}
+
+/// Writes deps files as the options say, keeping track of every change
+/// made.
+#[derive(Debug, Default)]
+pub struct Writer {
+    pub options: WriteOptions,
+    pub changes: Vec<Change>,
+}
+
+impl Writer {
+    pub fn new(options: WriteOptions) -> Writer {
+        Writer { options, changes: Vec::new() }
+    }
+
+    /// Replaces a file whose contents were `before` with `lines`.
+    pub fn write(&mut self, path: &Path, before: Option<&str>, lines: &Document) -> Result<(), Error> {
+        self.apply(Change {
+            path: path.to_owned(),
+            before: before.map(|b| b.to_owned()),
+            after: Some(lines.to_string()),
+        })
+    }
+
+    /// Makes a change, writing or removing the file as needed.
+    pub fn apply(&mut self, change: Change) -> Result<(), Error> {
+        if !self.options.dry_run {
+            put(&change.path, change.after.as_deref(), &self.options)?;
+        }
+        self.changes.push(change);
+        Ok(())
+    }
+
+    /// Puts back every file changed since the first `from` changes were
+    /// made, most recent first, and forgets those changes.
+    pub fn rollback(&mut self, from: usize) -> Result<(), Error> {
+        while self.changes.len() > from {
+            let change = self.changes.pop().unwrap();
+            if !self.options.dry_run {
+                put(&change.path, change.before.as_deref(), &WriteOptions::default())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Rolls back to the first `from` changes after a command failed
+    /// with `error`, returning the error to report.
+    pub fn abort(&mut self, from: usize, error: Error) -> Error {
+        match self.rollback(from) {
+            Ok(()) => error,
+            Err(rollback) => Error::RollbackFailed { error: Box::new(error), rollback: Box::new(rollback) },
+        }
+    }
+
+    /// Ends a command. In a dry run this prints a diff of each file that
+    /// would have changed and fails if there are any, so scripts can
+    /// tell. Otherwise the changes are added to the journal.
+    pub fn finish(self) -> Result<(), Error> {
+        if self.changes.is_empty() {
+            return Ok(());
+        }
+        if self.options.dry_run {
+            for change in &self.changes {
+                print!("{}", change.diff());
+            }
+            return Err(Error::WouldChange(self.changes.into_iter().map(|c| c.path).collect()));
+        }
+        match &self.options.journal {
+            Some(journal) => journal::record(journal, self.changes),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Gives a file the contents, or removes it if there are none.
+fn put(path: &Path, contents: Option<&str>, options: &WriteOptions) -> Result<(), Error> {
+    match contents {
+        Some(text) => write_text(text, path, options),
+        None => resolve_symlinks(path).and_then(fs::remove_file),
+    }.map_err(|e| Error::io(path, e))
+}
 
 pub fn get_refs(lines: &[Line], package: &str) -> Vec<usize> {
     lines.iter().enumerate().filter(|(_, line)| {
@@ -23,29 +140,76 @@ pub fn find_line<'a>(lines: &'a [Line], package: &str) -> Result<(&'a Line, usiz
     }
 }
 
-pub fn write_file(lines: &[Line], path: &Path) {
-    match File::create(path) {
-        Ok(mut file) => {
-            for line in lines {
-                write!(file, "{}\n", &line).unwrap();
+pub fn write_file(lines: &Document, path: &Path, options: &WriteOptions) -> Result<(), Error> {
+    write_lines(lines, path, options).map_err(|e| Error::io(path, e))
+}
+
+pub fn write_lines(lines: &Document, path: &Path, options: &WriteOptions) -> io::Result<()> {
+    write_text(&lines.to_string(), path, options)
+}
+
+/// Replaces a file with the given text.
+///
+/// The text is written to a temporary file next to the target, which
+/// is then renamed over it, so the target is never left half-written.
+/// If `path` is a symlink, the file it points to is replaced and the
+/// link is left alone.
+pub fn write_text(text: &str, path: &Path, options: &WriteOptions) -> io::Result<()> {
+    let target = resolve_symlinks(path)?;
+    let existing = match fs::metadata(&target) {
+        Ok(meta) => Some(meta),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    if options.backup && existing.is_some() {
+        fs::copy(&target, sibling(&target, ".orig"))?;
+    }
+    let temp = sibling(&target, &format!(".tmp{}", std::process::id()));
+    let written = write_temp(text, &temp, existing.as_ref())
+        .and_then(|_| fs::rename(&temp, &target));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
+
+fn write_temp(text: &str, temp: &Path, existing: Option<&fs::Metadata>) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(temp)?;
+    file.write_all(text.as_bytes())?;
+    if let Some(meta) = existing {
+        file.set_permissions(meta.permissions())?;
+    }
+    file.sync_all()
+}
+
+/// Follows symlinks until we reach something that is not one.
+fn resolve_symlinks(path: &Path) -> io::Result<PathBuf> {
+    let mut path = path.to_owned();
+    for _ in 0..40 {
+        match fs::symlink_metadata(&path) {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                let link = fs::read_link(&path)?;
+                path = match path.parent() {
+                    Some(parent) => parent.join(link),
+                    None => link,
+                };
             }
-        }
-        Err(error) => {
-            println!("Error writing {:?}: {}", path, error);
-            exit(1);
+            Ok(_) => return Ok(path),
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(path),
+            Err(e) => return Err(e),
         }
     }
+    Err(io::Error::other("too many levels of symbolic links"))
 }
 
-pub fn write_lines(lines: &[Line], path: &Path) -> Result<(), Error> {
-    let mut file = File::create(path)?;
-    for line in lines {
-        write!(file, "{}\n", &line)?;
-    }
-    Ok(())
+/// The path with a suffix added to its file name.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(suffix);
+    path.with_file_name(name)
 }
 
-pub fn add_new_line(package: &str, version: &str, lines: &mut Vec<Line>, path: &Path) -> Result<(), ChangeError> {
+pub fn add_new_line(package: &str, version: &str, lines: &mut Document, path: &Path) -> Result<(), ChangeError> {
     match find_line(&*lines, package) {
         Ok((_, _)) => Err(ChangeError::AlreadyExists),
         Err(FindError::OccursMany) => Err(ChangeError::OccursMany),
@@ -53,43 +217,70 @@ pub fn add_new_line(package: &str, version: &str, lines: &mut Vec<Line>, path: &
             adding(package, version, path);
             let package = Package::new(package, version);
             lines.push(Line::Enabled(Enabled::new("", "", package)));
-            write_lines(&*lines, path).map_err(ChangeError::IO)?;
             Ok(())
         }
     }
 }
 
-pub fn add_or_update_line(package: &str, version: &str, lines: &mut Vec<Line>, path: &Path) -> Result<(), ChangeError> {
+pub fn add_or_update_line(package: &str, version: &str, lines: &mut Document, path: &Path) -> Result<(), ChangeError> {
     match find_line(&*lines, package) {
         Ok((_, index)) => {
             lines[index].update(version, path);
-            write_lines(&*lines, path).map_err(ChangeError::IO)?;
             Ok(())
         }
         Err(FindError::Missing) => {
             adding(package, version, path);
             let package = Package::new(package, version);
             lines.push(Line::Enabled(Enabled::new("", "", package)));
-            write_lines(&*lines, path).map_err(ChangeError::IO)?;
             Ok(())
         }
         Err(FindError::OccursMany) => Err(ChangeError::OccursMany)
     }
 }
 
-pub fn update_existing_line(package: &str, version: &str, lines: &mut Vec<Line>, path: &Path) -> Result<(), ChangeError> {
+/// Gives a package an enabled line with `version`, either a new one or
+/// its existing line, enabled. `post`, a trailing comment carried over
+/// from elsewhere, is kept unless the line already has one.
+pub fn add_or_replace_line(package: &str, version: &str, post: &str, lines: &mut Document, path: &Path) -> Result<(), ChangeError> {
+    let index = match find_line(&*lines, package) {
+        Ok((_, index)) => {
+            lines[index].update(version, path);
+            index
+        }
+        Err(FindError::Missing) => {
+            adding(package, version, path);
+            lines.push(Line::Enabled(Enabled::new("", "", Package::new(package, version))));
+            lines.len() - 1
+        }
+        Err(FindError::OccursMany) => return Err(ChangeError::OccursMany),
+    };
+    let mut enabled = match lines[index].clone() {
+        Line::Disabled(d) => {
+            println!("{:?}: enabling", path);
+            d.enable()
+        }
+        Line::Enabled(e) => e,
+        _ => unreachable!(),
+    };
+    if enabled.post.trim().is_empty() {
+        enabled.post = post.to_string();
+    }
+    lines[index] = Line::Enabled(enabled);
+    Ok(())
+}
+
+pub fn update_existing_line(package: &str, version: &str, lines: &mut Document, path: &Path) -> Result<(), ChangeError> {
     match find_line(&*lines, package) {
         Err(FindError::Missing) => Err(ChangeError::Missing),
         Err(FindError::OccursMany) => Err(ChangeError::OccursMany),
         Ok((_, index)) => {
             lines[index].update(version, path);
-            write_lines(&*lines, path).map_err(ChangeError::IO)?;
             Ok(())
         }
     }
 }
 
-pub fn disable_existing_line(package: &str, lines: &mut Vec<Line>, path: &Path) -> Result<(), ChangeError> {
+pub fn disable_existing_line(package: &str, marker: &str, lines: &mut Document, path: &Path) -> Result<(), ChangeError> {
     match find_line(&*lines, package) {
         Ok((Line::Disabled(_), _)) => {
             println!("{:?}: already disabled", path);
@@ -97,8 +288,7 @@ pub fn disable_existing_line(package: &str, lines: &mut Vec<Line>, path: &Path)
         }
         Ok((Line::Enabled(e), index)) => {
             println!("{:?}: disabling", path);
-            lines[index] = Line::Disabled(e.clone().disable());
-            write_lines(&*lines, path).map_err(ChangeError::IO)?;
+            lines[index] = Line::Disabled(e.clone().disable(marker));
             Ok(())
         }
         Err(FindError::Missing) => Err(ChangeError::Missing),
@@ -107,7 +297,7 @@ pub fn disable_existing_line(package: &str, lines: &mut Vec<Line>, path: &Path)
     }
 }
 
-pub fn disable_line_if_present(package: &str, lines: &mut Vec<Line>, path: &Path) -> Result<(), ChangeError> {
+pub fn disable_line_if_present(package: &str, marker: &str, lines: &mut Document, path: &Path) -> Result<(), ChangeError> {
     match find_line(&*lines, package) {
         Ok((Line::Disabled(_), _)) => {
             println!("{:?}: already disabled", path);
@@ -115,8 +305,7 @@ pub fn disable_line_if_present(package: &str, lines: &mut Vec<Line>, path: &Path
         }
         Ok((Line::Enabled(e), index)) => {
             println!("{:?}: disabling", path);
-            lines[index] = Line::Disabled(e.clone().disable());
-            write_lines(&*lines, path).map_err(ChangeError::IO)?;
+            lines[index] = Line::Disabled(e.clone().disable(marker));
             Ok(())
         }
         Err(FindError::Missing) => {
@@ -128,12 +317,11 @@ pub fn disable_line_if_present(package: &str, lines: &mut Vec<Line>, path: &Path
     }
 }
 
-pub fn enable_existing_line(package: &str, lines: &mut Vec<Line>, path: &Path) -> Result<(), ChangeError> {
+pub fn enable_existing_line(package: &str, lines: &mut Document, path: &Path) -> Result<(), ChangeError> {
     match find_line(&*lines, package) {
         Ok((Line::Disabled(d), index)) => {
             println!("{:?}: enabling", path);
             lines[index] = Line::Enabled(d.clone().enable());
-            write_lines(&*lines, path).map_err(ChangeError::IO)?;
             Ok(())
         }
         Ok((Line::Enabled(_), _)) => {
@@ -146,12 +334,11 @@ pub fn enable_existing_line(package: &str, lines: &mut Vec<Line>, path: &Path) -
     }
 }
 
-pub fn enable_line_if_present(package: &str, lines: &mut Vec<Line>, path: &Path) -> Result<(), ChangeError> {
+pub fn enable_line_if_present(package: &str, lines: &mut Document, path: &Path) -> Result<(), ChangeError> {
     match find_line(&*lines, package) {
         Ok((Line::Disabled(d), index)) => {
             println!("{:?}: enabling", path);
             lines[index] = Line::Enabled(d.clone().enable());
-            write_lines(&*lines, path).map_err(ChangeError::IO)?;
             Ok(())
         }
         Ok((Line::Enabled(_), _)) => {
@@ -167,3 +354,71 @@ pub fn enable_line_if_present(package: &str, lines: &mut Vec<Line>, path: &Path)
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory of its own for a test to write into.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mess-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn change(path: &Path, before: Option<&str>, after: Option<&str>) -> Change {
+        Change { path: path.to_owned(), before: before.map(String::from), after: after.map(String::from) }
+    }
+
+    #[test]
+    fn abort_puts_back_files_written_before_the_failure() {
+        let dir = scratch("abort");
+        let (edited, created) = (dir.join("deps.hex"), dir.join("deps.git"));
+        fs::write(&edited, "foo = \"1\"\n").unwrap();
+        let mut writer = Writer::default();
+        writer.apply(change(&edited, Some("foo = \"1\"\n"), Some("foo = \"2\"\n"))).unwrap();
+        writer.apply(change(&created, None, Some("bar = \"1\"\n"))).unwrap();
+        let error = writer.apply(change(&dir.join("missing/deps.path"), None, Some("x"))).unwrap_err();
+        match writer.abort(0, error) {
+            Error::IO { path, .. } => assert_eq!(path, dir.join("missing/deps.path")),
+            e => panic!("unexpected error {:?}", e),
+        }
+        assert_eq!(fs::read_to_string(&edited).unwrap(), "foo = \"1\"\n");
+        assert!(!created.exists());
+        assert!(writer.changes.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn abort_keeps_earlier_changes() {
+        let dir = scratch("abort-from");
+        let (first, second) = (dir.join("deps.hex"), dir.join("deps.git"));
+        let mut writer = Writer::default();
+        writer.apply(change(&first, None, Some("a"))).unwrap();
+        writer.apply(change(&second, None, Some("b"))).unwrap();
+        writer.abort(1, Error::NoSpecs);
+        assert_eq!(fs::read_to_string(&first).unwrap(), "a");
+        assert!(!second.exists());
+        assert_eq!(writer.changes.len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn abort_reports_a_failed_rollback_with_the_error() {
+        let dir = scratch("abort-fails");
+        let mut writer = Writer::default();
+        fs::create_dir(dir.join("sub")).unwrap();
+        writer.apply(change(&dir.join("sub/deps.hex"), Some("a"), Some("b"))).unwrap();
+        // nowhere left to put the old contents back
+        fs::remove_dir_all(dir.join("sub")).unwrap();
+        match writer.abort(0, Error::NoSpecs) {
+            Error::RollbackFailed { error, rollback } => {
+                assert!(matches!(*error, Error::NoSpecs));
+                assert!(matches!(*rollback, Error::IO { .. }));
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
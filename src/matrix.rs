@@ -0,0 +1,87 @@
+//! Which spec each flavour uses for each package, as reported by
+//! `messctl matrix`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use serde::Serialize;
+use crate::*;
+use crate::config::{under, Config};
+use crate::project::Project;
+
+/// What one flavour has for a package: the entry mess would use, or if
+/// it is disabled everywhere, the disabled entry with the highest
+/// precedence.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Cell {
+    pub source: Source,
+    pub spec: String,
+    pub enabled: bool,
+}
+
+impl Cell {
+    fn of(project: &Project, package: &str) -> Option<Cell> {
+        let mut disabled = None;
+        for source in Source::ALL.iter().rev().copied() {
+            let entries = project.file(source).entries();
+            for entry in entries.into_iter().filter(|e| e.package == package) {
+                let cell = Cell { source, spec: entry.spec, enabled: entry.enabled };
+                if cell.enabled {
+                    return Some(cell);
+                }
+                disabled = disabled.or(Some(cell));
+            }
+        }
+        disabled
+    }
+}
+
+impl fmt::Display for Cell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.enabled {
+            write!(f, "# ")?;
+        }
+        write!(f, "{}: {}", self.source, self.spec)
+    }
+}
+
+/// A package across every flavour.
+#[derive(Clone, Debug, Serialize)]
+pub struct Row {
+    pub package: String,
+    /// Whether the flavours disagree about the package.
+    pub drift: bool,
+    /// By flavour name, `None` where the flavour does not have it.
+    pub flavours: BTreeMap<String, Option<Cell>>,
+}
+
+/// Loads every flavour's deps files and lines up their packages.
+/// Returns the flavour names and a row per package, both sorted. Fails
+/// if there are flavours but none of them has any deps files, as they
+/// are then most likely somewhere the config does not say.
+pub fn matrix(config: &Config) -> Result<(Vec<String>, Vec<Row>), Error> {
+    let flavours = config.flavours()?;
+    let mut projects = Vec::new();
+    for flavour in &flavours {
+        let file = |source: Source| config.flavour_file(flavour, config.file(source));
+        projects.push(Project::from_files(file(Source::Hex), file(Source::Git), file(Source::Path))?);
+    }
+    let found = projects.iter().any(|p| Source::ALL.iter().any(|s| p.file(*s).exists()));
+    if !flavours.is_empty() && !found {
+        let files = config.all_files().iter()
+            .map(|f| f.strip_prefix(&config.root).unwrap_or(f).to_owned())
+            .collect();
+        return Err(Error::NoFlavourFiles { dir: under(&config.root, &config.flavours), files });
+    }
+    let mut packages: Vec<String> = projects.iter()
+        .flat_map(|p| Source::ALL.iter().flat_map(move |s| p.file(*s).entries()))
+        .map(|e| e.package)
+        .collect();
+    packages.sort();
+    packages.dedup();
+    let rows = packages.into_iter().map(|package| {
+        let cells: Vec<Option<Cell>> = projects.iter().map(|p| Cell::of(p, &package)).collect();
+        let drift = cells.windows(2).any(|pair| pair[0] != pair[1]);
+        Row { package, drift, flavours: flavours.iter().cloned().zip(cells).collect() }
+    }).collect();
+    Ok((flavours, rows))
+}
@@ -1,37 +1,179 @@
 use std::path::PathBuf;
 use structopt::StructOpt;
 use crate::*;
+use crate::config::{under, Config};
+use crate::diagnostics::Diagnostic;
+use crate::project::{DepsFile, Entry, Project, Source};
 
+/// Edit mess deps files.
+///
+/// Commands that take FILES fall back to those set for them under
+/// `[defaults]` in `.messctl.toml`.
 #[derive(Debug, StructOpt)]
 #[structopt(name="messctl")]
-pub enum Opt {
-    /// Add a package with the given version to some deps files, or update it.
+pub struct Opt {
+    /// Keep the previous version of each changed deps file as `<file>.orig`
+    #[structopt(long, global=true)]
+    pub backup: bool,
+    /// Don't write anything, print a diff of each file that would change
+    /// and exit with status 2 if there are any
+    #[structopt(long="dry-run", global=true)]
+    pub dry_run: bool,
+    /// Work on the project in PROJECT instead of the one the current
+    /// directory is in
+    #[structopt(short="C", long="project", name="PROJECT", global=true, parse(from_os_str))]
+    pub project: Option<PathBuf>,
+    #[structopt(subcommand)]
+    pub command: Command,
+}
+
+/// Which flavours' copies of the deps files a command edits.
+#[derive(Debug, StructOpt)]
+pub struct Flavours {
+    /// Edit the FILES of this flavour instead, e.g. `config/deps.hex` in
+    /// `flavours/NAME/`. May be given more than once.
+    #[structopt(long="flavour", name="NAME", number_of_values=1)]
+    pub names: Vec<String>,
+    /// Edit the FILES of every flavour that has them
+    #[structopt(long="all-flavours", conflicts_with="NAME")]
+    pub all: bool,
+}
+
+impl Flavours {
+    /// Fills in the names of every flavour if all were chosen, checking
+    /// they exist. The names are empty if no flavours were chosen.
+    fn resolve(self, config: &Config) -> Result<Flavours, Error> {
+        if self.all {
+            let names = config.flavours()?;
+            if names.is_empty() {
+                return Err(Error::NoSuchFile(under(&config.root, &config.flavours)));
+            }
+            return Ok(Flavours { names, all: true });
+        }
+        for name in &self.names {
+            config.check_flavour(name)?;
+        }
+        Ok(self)
+    }
+}
+
+/// Packages paired with the specs to give them.
+type PackageSpecs = Vec<(String, String)>;
+
+/// The packages and specs `add` and `update` work on, and their files.
+#[derive(Debug, StructOpt)]
+pub struct Specs {
+    /// `PACKAGE DEPSPEC` or any number of `PACKAGE=DEPSPEC`, then FILES
+    #[structopt(name="ARGS")]
+    pub args: Vec<String>,
+    /// More FILES, after `--`
+    #[structopt(name="FILES", parse(from_os_str), last=true)]
+    pub files: Vec<PathBuf>,
+    /// Read more packages from a file with a `PACKAGE=DEPSPEC` per line
+    #[structopt(long="from-file", name="SPECS", parse(from_os_str))]
+    pub from_file: Option<PathBuf>,
+}
+
+impl Specs {
+    /// Splits the arguments into packages with their specs, and files.
+    /// `locate` finds where an existing file given before `--` is.
+    ///
+    /// A file before `--` that does not exist may only be created with
+    /// `PACKAGE DEPSPEC` and if `create` is set; otherwise new files must
+    /// come after `--`. That way a stray `PACKAGE DEPSPEC` is not taken
+    /// for two new files, and a deps file is not made in a subdirectory
+    /// by mistake.
+    fn resolve(self, locate: impl Fn(&Path) -> Option<PathBuf>, create: bool) -> Result<(PackageSpecs, Vec<PathBuf>), Error> {
+        let mut args = self.args.into_iter().peekable();
+        let mut specs = Vec::new();
+        while let Some(spec) = args.next_if(|a| a.contains('=')) {
+            let (package, version) = spec.split_once('=').unwrap();
+            specs.push((package.trim().to_string(), version.trim().to_string()));
+        }
+        if let Some(path) = &self.from_file {
+            specs.extend(read_specs(path)?);
+        }
+        let named = !specs.is_empty();
+        if !named {
+            match (args.next(), args.next()) {
+                (Some(package), Some(version)) => specs.push((package, version)),
+                _ => return Err(Error::NoSpecs),
+            }
+        }
+        for (i, (package, version)) in specs.iter().enumerate() {
+            validate_package_name(package)?;
+            check_value(version)?;
+            if specs[..i].iter().any(|(p, _)| p == package) {
+                return Err(Error::DuplicateSpec(package.clone()));
+            }
+        }
+        let mut files = Vec::new();
+        for file in args.map(PathBuf::from) {
+            match locate(&file) {
+                Some(found) => files.push(found),
+                None if create && !named => files.push(file),
+                None => return Err(Error::NewFileNotAfterSeparator(file)),
+            }
+        }
+        files.extend(self.files);
+        Ok((specs, files))
+    }
+}
+
+/// Reads `PACKAGE=DEPSPEC` lines, skipping blank lines and `#` comments.
+fn read_specs(path: &Path) -> Result<PackageSpecs, Error> {
+    let text = read_file(path)?.ok_or_else(|| Error::NoSuchFile(path.to_owned()))?;
+    let mut specs = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        match trimmed.split_once('=') {
+            Some((package, version)) => specs.push((package.trim().to_string(), version.trim().to_string())),
+            None => {
+                let message = "expected PACKAGE=DEPSPEC";
+                return Err(Error::Parse(Diagnostic::new(path, i + 1, line.len(), message, line)));
+            }
+        }
+    }
+    Ok(specs)
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// Add packages with the given versions to some deps files, or update them.
+    ///
+    /// Takes `PACKAGE DEPSPEC FILES...`, or for several packages at once
+    /// `PACKAGE=DEPSPEC... -- FILES...`. Each file is written once. A
+    /// file not in the current directory is looked for in the project;
+    /// to create one outside the project directory, give it after `--`.
     #[structopt(aliases=&["ad"])]
     Add {
-        /// Name of package
-        #[structopt(name="PACKAGE")]
-        package: String,
-        #[structopt(name="DEPSPEC")]
-        version: String,
-        #[structopt(name="FILES", parse(from_os_str), required=true, min_values=1)]
-        files: Vec<PathBuf>,
+        #[structopt(flatten)]
+        specs: Specs,
         /// If the package exists, suppress updating the version 
         #[structopt(long = "no-update", parse(from_flag = std::ops::Not::not))]
         update: bool,
+        #[structopt(flatten)]
+        flavours: Flavours,
     },
 
-    /// Update the version of a package in some deps files, or add it.
+    /// Update the versions of packages in some deps files, or add them.
+    ///
+    /// Takes `PACKAGE DEPSPEC FILES...`, or for several packages at once
+    /// `PACKAGE=DEPSPEC... -- FILES...`. Each file is written once. A
+    /// file not in the current directory is looked for in the project;
+    /// to create one outside the project directory, give it after `--`.
     #[structopt(alias="up")]
     Update {
-        #[structopt(name="PACKAGE")]
-        package: String,
-        #[structopt(name="DEPSPEC")]
-        version: String,
-        #[structopt(name="FILES", parse(from_os_str), required=true, min_values=1)]
-        files: Vec<PathBuf>,
+        #[structopt(flatten)]
+        specs: Specs,
         /// If the package does not exist, suppress adding it
         #[structopt(long = "no-add", parse(from_flag = std::ops::Not::not))]
         add: bool,
+        #[structopt(flatten)]
+        flavours: Flavours,
     },
 
     /// Delete a package from some deps files if it is present.
@@ -39,8 +181,10 @@ pub enum Opt {
     Delete {
         #[structopt(name="PACKAGE")]
         package: String,
-        #[structopt(name="FILES", parse(from_os_str), required=true, min_values=1)]
+        #[structopt(name="FILES", parse(from_os_str))]
         files: Vec<PathBuf>,
+        #[structopt(flatten)]
+        flavours: Flavours,
     },
 
     /// Uncomment a package in some deps files
@@ -48,8 +192,10 @@ pub enum Opt {
     Enable {
         #[structopt(name="PACKAGE")]
         package: String,
-        #[structopt(name="FILES", parse(from_os_str), required=true, min_values=1)]
+        #[structopt(name="FILES", parse(from_os_str))]
         files: Vec<PathBuf>,
+        #[structopt(flatten)]
+        flavours: Flavours,
     },
 
     /// Comment a package in some deps files
@@ -57,102 +203,554 @@ pub enum Opt {
     Disable {
         #[structopt(name="PACKAGE")]
         package: String,
-        #[structopt(name="FILES", parse(from_os_str), required=true, min_values=1)]
+        #[structopt(name="FILES", parse(from_os_str))]
         files: Vec<PathBuf>,
+        #[structopt(flatten)]
+        flavours: Flavours,
+    },
+
+    /// Take a package from one source only.
+    ///
+    /// Enables the package's entry in the deps file for SOURCE and
+    /// disables it in the other two. Fails without writing anything if
+    /// that file has no entry for it.
+    Use {
+        #[structopt(name="PACKAGE")]
+        package: String,
+        /// hex, git or path
+        #[structopt(name="SOURCE")]
+        source: Source,
+    },
+
+    /// Move a package to another deps file with a new spec.
+    ///
+    /// The entry keeps its trailing comment. The old entry is left
+    /// disabled unless `--remove` is given. Moves from the source the
+    /// package is taken from unless `--from` says otherwise.
+    Move {
+        #[structopt(name="PACKAGE")]
+        package: String,
+        /// Source to move to: hex, git or path
+        #[structopt(long, name="TO")]
+        to: Source,
+        /// Spec for the new entry, e.g. `https://github.com/org/repo#main`
+        #[structopt(long, name="DEPSPEC")]
+        spec: String,
+        /// Source to move from: hex, git or path
+        #[structopt(long, name="FROM")]
+        from: Option<Source>,
+        /// Remove the old entry instead of disabling it
+        #[structopt(long)]
+        remove: bool,
+    },
+
+    /// List the packages in some deps files.
+    ///
+    /// Defaults to the files configured for `list` in `.messctl.toml`,
+    /// or else `deps.hex`, `deps.git` and `deps.path`, skipping any that
+    /// do not exist.
+    #[structopt(aliases=&["ls"])]
+    List {
+        #[structopt(name="FILES", parse(from_os_str))]
+        files: Vec<PathBuf>,
+        /// Print JSON instead of a table
+        #[structopt(long)]
+        json: bool,
+        /// Only list enabled packages
+        #[structopt(long, conflicts_with="disabled")]
+        enabled: bool,
+        /// Only list disabled packages
+        #[structopt(long)]
+        disabled: bool,
+        /// Only list packages from the deps file for this source: hex, git or path
+        #[structopt(long, name="SOURCE")]
+        source: Option<Source>,
+    },
+
+    /// Show where a package appears and which source mess will use.
+    ///
+    /// Looks in `deps.path`, `deps.git` and `deps.hex`. The enabled
+    /// entry from the first of those wins.
+    Show {
+        #[structopt(name="PACKAGE")]
+        package: String,
+    },
+
+    /// Compare packages across flavours.
+    ///
+    /// Prints a table with a row per package and a column per flavour,
+    /// showing the spec each flavour uses and whether it is enabled.
+    /// Packages that differ between flavours are marked with `*`.
+    Matrix {
+        /// Print JSON instead of a table
+        #[structopt(long, conflicts_with="csv")]
+        json: bool,
+        /// Print CSV instead of a table
+        #[structopt(long)]
+        csv: bool,
+        /// Only show packages that differ between flavours
+        #[structopt(long)]
+        drift: bool,
+    },
+
+    /// Check some deps files for problems, exiting non-zero if any are found.
+    ///
+    /// Reports malformed lines, duplicate entries, packages enabled in
+    /// more than one source, values in the wrong deps file and
+    /// `deps.path` entries whose directory is missing. Defaults to the
+    /// configured files, or else the project's deps files.
+    Check {
+        #[structopt(name="FILES", parse(from_os_str))]
+        files: Vec<PathBuf>,
+    },
+
+    /// Rewrite some deps files in canonical form.
+    ///
+    /// Dependencies become `name = "value"`, disabled ones `# name = "value"`,
+    /// and comment markers `# `. Defaults to the configured files, or else
+    /// the project's deps files.
+    Fmt {
+        #[structopt(name="FILES", parse(from_os_str))]
+        files: Vec<PathBuf>,
+        /// Don't write anything, fail if any file is not formatted
+        #[structopt(long)]
+        check: bool,
+        /// Sort entries by name within each section between comments or blank lines
+        #[structopt(long)]
+        sort: bool,
+    },
+
+    /// Revert the last N commands that changed deps files.
+    ///
+    /// Every command that writes a deps file records what it changed in
+    /// `.mess/journal.jsonl`. Refuses if a file has been changed since.
+    /// Clones made by `messflow fork` are left alone.
+    Undo {
+        #[structopt(name="N", default_value="1")]
+        count: usize,
     },
 }
 
-pub fn run() {
-    run_opt(Opt::from_args());
+pub fn run() -> Result<(), Error> {
+    run_opt(Opt::from_args())
 }
 
-pub fn run_opt(opt: Opt) {
-    use Opt::*;
-    match opt {
-        Add { package, version, files, update } => {
-            for f in files {
-                add(&package, &version, update, &f)
-            }
+pub fn run_opt(opt: Opt) -> Result<(), Error> {
+    use Command::*;
+    // undoing is not itself something to undo
+    let config = Config::discover(opt.project.as_deref())?;
+    let journal = match opt.command {
+        Undo { .. } => None,
+        _ => Some(config.journal()),
+    };
+    let mut writer = Writer::new(WriteOptions { backup: opt.backup, dry_run: opt.dry_run, journal });
+    let result = run_command(opt.command, &config, &mut writer);
+    // journal whatever was written, even if the command failed part way
+    result.and(writer.finish())
+}
+
+fn run_command(command: Command, config: &Config, writer: &mut Writer) -> Result<(), Error> {
+    use Command::*;
+    let defaults = &config.defaults;
+    match command {
+        Add { specs, update, flavours } => {
+            let flavours = flavours.resolve(config)?;
+            let create = !flavours.names.is_empty() || config.root == Path::new(".");
+            let (specs, files) = specs.resolve(|f| locate(f, &flavours.names, config), create)?;
+            let files = files_or_configured(files, &defaults.add, "add")?;
+            each_file(&files, flavours, config, writer, |f, writer| {
+                edit_specs(&specs, f, config, writer, |package, version, lines, path| {
+                    if update {
+                        add_or_update_line(package, version, lines, path)
+                    } else {
+                        add_new_line(package, version, lines, path)
+                    }
+                })
+            })?;
         }
-        Delete { package, files } => {
-            for f in files {
-                delete(&package, &f);
-            }
+        Delete { package, files, flavours } => {
+            let flavours = flavours.resolve(config)?;
+            let files = files_or_configured(locate_all(files, &flavours.names, config), &defaults.delete, "delete")?;
+            each_file(&files, flavours, config, writer, |f, writer| {
+                delete(&package, f, writer)
+            })?;
         }
-        Update { package, version, files, add } => {
-            for f in files {
-                update(&package, &version, add, &f)
-             }
+        Update { specs, add, flavours } => {
+            let flavours = flavours.resolve(config)?;
+            let create = !flavours.names.is_empty() || config.root == Path::new(".");
+            let (specs, files) = specs.resolve(|f| locate(f, &flavours.names, config), create)?;
+            let files = files_or_configured(files, &defaults.update, "update")?;
+            each_file(&files, flavours, config, writer, |f, writer| {
+                edit_specs(&specs, f, config, writer, |package, version, lines, path| {
+                    if add {
+                        add_or_update_line(package, version, lines, path)
+                    } else {
+                        update_existing_line(package, version, lines, path)
+                    }
+                })
+            })?;
         }
-        Enable { package, files } => {
-            for f in files {
-                enable(&package, &f)
-            }
+        Enable { package, files, flavours } => {
+            let flavours = flavours.resolve(config)?;
+            let files = files_or_configured(locate_all(files, &flavours.names, config), &defaults.enable, "enable")?;
+            each_file(&files, flavours, config, writer, |f, writer| {
+                enable(&package, f, writer)
+            })?;
         }
-        Disable { package, files } => {
-            for f in files {
-                disable(&package, &f);
-            }
+        Disable { package, files, flavours } => {
+            let flavours = flavours.resolve(config)?;
+            let files = files_or_configured(locate_all(files, &flavours.names, config), &defaults.disable, "disable")?;
+            each_file(&files, flavours, config, writer, |f, writer| {
+                disable(&package, &config.comment_marker, f, writer)
+            })?;
+        }
+        Use { package, source } => {
+            let mut project = Project::load(config)?;
+            project.return_to(&package, source)?;
+            project.save(writer)?;
+        }
+        Move { package, to, spec, from, remove } => {
+            let mut project = Project::load(config)?;
+            project.move_to(&package, from, to, &spec, remove)?;
+            project.save(writer)?;
+        }
+        List { files, json, enabled, disabled, source } => {
+            let state = if enabled { Some(true) } else if disabled { Some(false) } else { None };
+            let files = locate_all(files, &[], config);
+            list(&files, &files_or_defaults(&files, &defaults.list, config), config, json, state, source)?;
+        }
+        Show { package } => {
+            show(&package, config)?;
+        }
+        Matrix { json, csv, drift } => {
+            matrix(config, json, csv, drift)?;
+        }
+        Check { files } => {
+            let files = locate_all(files, &[], config);
+            check(&files_or_defaults(&files, &defaults.check, config), config)?;
+        }
+        Fmt { files, check, sort } => {
+            let files = locate_all(files, &[], config);
+            fmt(&files_or_defaults(&files, &defaults.fmt, config), check, sort, &config.comment_marker, writer)?;
+        }
+        Undo { count } => {
+            journal::undo(&config.journal(), count, writer)?;
         }
     }
+    Ok(())
 }
 
-fn add(package: &str, version: &str, update: bool, file: &Path) {
-    parse_package(package).expect("package name to be valid");
-    let mut lines = parse_file(file);
-    let ret = if update {
-        add_or_update_line(package, version, &mut lines, file)
+/// Applies an edit for each package and spec to a deps file, which is
+/// read and written once. With several packages, reports which of them
+/// changed.
+fn edit_specs<F>(specs: &[(String, String)], file: &Path, config: &Config, writer: &mut Writer, edit: F) -> Result<(), Error>
+where F: Fn(&str, &str, &mut Document, &Path) -> Result<(), ChangeError> {
+    for (_, version) in specs {
+        check_spec(version, config.source_of(file))?;
+    }
+    let mut deps = DepsFile::load(file)?;
+    let mut changed = Vec::new();
+    for (package, version) in specs {
+        let line = |deps: &DepsFile| deps.find(package).map(|found| found.map(|(l, _)| l.to_string()));
+        let before = line(&deps)?;
+        deps.edit(package, |lines, path| edit(package, version, lines, path))?;
+        if line(&deps)? != before {
+            changed.push(package.as_str());
+        }
+    }
+    deps.save(writer)?;
+    if specs.len() > 1 && changed.is_empty() {
+        println!("{:?}: no changes", file);
+    } else if specs.len() > 1 {
+        println!("{:?}: changed {}", file, changed.join(", "));
+    }
+    Ok(())
+}
+
+fn disable(package: &str, marker: &str, file: &Path, writer: &mut Writer) -> Result<(), Error> {
+    validate_package_name(package)?;
+    let mut deps = DepsFile::load(file)?;
+    deps.edit(package, |lines, path| disable_line_if_present(package, marker, lines, path))?;
+    deps.save(writer)?;
+    Ok(())
+}
+
+fn enable(package: &str, file: &Path, writer: &mut Writer) -> Result<(), Error> {
+    validate_package_name(package)?;
+    let mut deps = DepsFile::load(file)?;
+    deps.edit(package, |lines, path| enable_existing_line(package, lines, path))?;
+    deps.save(writer)?;
+    Ok(())
+}
+
+fn delete(package: &str, file: &Path, writer: &mut Writer) -> Result<(), Error> {
+    validate_package_name(package)?;
+    let mut deps = DepsFile::load(file)?;
+    let mut refs = get_refs(&deps.lines, package);
+    refs.reverse();
+    for r in refs {
+        println!("Deleting package {} from file {:?}", package, file);
+        deps.lines.remove(r);
+    }
+    deps.save(writer)?;
+    Ok(())
+}
+
+/// `given` are the files named on the command line, which must exist.
+fn list(given: &[PathBuf], files: &[PathBuf], config: &Config, json: bool, enabled: Option<bool>, source: Option<Source>) -> Result<(), Error> {
+    let mut entries = Vec::new();
+    for file in files {
+        let mut deps = DepsFile::load(file)?;
+        deps.source = config.source_of(file);
+        if !deps.exists() && !given.is_empty() {
+            return Err(Error::NoSuchFile(file.clone()));
+        }
+        entries.extend(deps.entries().into_iter().filter(|e| {
+            enabled.is_none_or(|enabled| e.enabled == enabled)
+                && source.is_none_or(|source| e.source == Some(source))
+        }));
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&entries).unwrap());
     } else {
-        add_new_line(package, version, &mut lines, file)
+        print_entries(&entries);
+    }
+    Ok(())
+}
+
+fn show(package: &str, config: &Config) -> Result<(), Error> {
+    validate_package_name(package)?;
+    let project = Project::load(config)?;
+    let occurrences = project.occurrences(package);
+    if occurrences.is_empty() {
+        let error = Error::NotInProject { package: package.to_owned(), suggestion: None };
+        return Err(error.suggesting(project.suggest(package)));
+    }
+    print_entries(&occurrences);
+    let enabled: Vec<&Entry> = occurrences.iter().filter(|e| e.enabled).collect();
+    match enabled.first().map(|winner| (winner, winner.source)) {
+        Some((winner, Some(source))) => println!("\nEffective source: {} ({}:{})",
+                                             source, winner.file.display(), winner.line),
+        _ => println!("\nEffective source: none, {} is disabled everywhere", package),
+    }
+    let mut sources: Vec<Source> = enabled.iter().filter_map(|e| e.source).collect();
+    sources.dedup();
+    if sources.len() > 1 {
+        let sources: Vec<String> = sources.iter().map(|s| s.to_string()).collect();
+        eprintln!("Warning: {} is enabled in more than one source: {}", package, sources.join(", "));
+    }
+    for source in Source::ALL.iter().rev() {
+        let count = occurrences.iter().filter(|e| e.source == Some(*source)).count();
+        if count > 1 {
+            eprintln!("Warning: {} occurs {} times in {}", package, count, config.file(*source).display());
+        }
+    }
+    Ok(())
+}
+
+fn matrix(config: &Config, json: bool, csv: bool, drift_only: bool) -> Result<(), Error> {
+    let (flavours, mut rows) = matrix::matrix(config)?;
+    if flavours.is_empty() {
+        return Err(Error::NoSuchFile(under(&config.root, &config.flavours)));
+    }
+    if drift_only {
+        rows.retain(|r| r.drift);
+    }
+    let cells = |row: &matrix::Row| -> Vec<String> {
+        row.flavours.values().map(|c| c.as_ref().map(|c| c.to_string()).unwrap_or_default()).collect()
     };
-    if let Err(e) = ret {
-        println!("Error: {:?} in file {:?}", e, file);
-        exit(1);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&rows).unwrap());
+    } else if csv {
+        let header: Vec<String> = ["package", "drift"].iter().map(|h| h.to_string()).chain(flavours).collect();
+        println!("{}", csv_record(&header));
+        for row in &rows {
+            let record: Vec<String> = vec![row.package.clone(), row.drift.to_string()]
+                .into_iter().chain(cells(row)).collect();
+            println!("{}", csv_record(&record));
+        }
+    } else {
+        let header: Vec<&str> = std::iter::once("  PACKAGE").chain(flavours.iter().map(|f| f.as_str())).collect();
+        let table: Vec<Vec<String>> = rows.iter().map(|row| {
+            let marker = if row.drift { "* " } else { "  " };
+            std::iter::once(format!("{}{}", marker, row.package)).chain(cells(row)).collect()
+        }).collect();
+        print_table(&header, &table);
+        if rows.iter().any(|r| r.drift) {
+            println!("\n* differs between flavours");
+        }
     }
+    Ok(())
 }
 
-fn update(package: &str, version: &str, add: bool, file: &Path) {
-    parse_package(package).expect("package name to be valid");
-    let mut lines = parse_file(file);
-    let ret = if add {
-        add_or_update_line(package, version, &mut lines, file)
+/// A line of CSV, quoting fields that need it.
+fn csv_record(fields: &[String]) -> String {
+    let fields: Vec<String> = fields.iter().map(|f| {
+        if f.contains([',', '"', '\n', '\r']) { format!("\"{}\"", f.replace('"', "\"\"")) } else { f.clone() }
+    }).collect();
+    fields.join(",")
+}
+
+fn check(files: &[PathBuf], config: &Config) -> Result<(), Error> {
+    let problems = check::check_files(files, config)?;
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    if problems.is_empty() {
+        Ok(())
     } else {
-        update_existing_line(package, version, &mut lines, file)
-    };
-    if let Err(e) = ret {
-        println!("Error: {:?} in file {:?}", e, file);
-        exit(1);
+        Err(Error::CheckFailed(problems.len()))
     }
 }
 
-fn disable(package: &str, file: &Path) {
-    parse_package(package).expect("package name to be valid");
-    let mut lines = parse_file(file);
-    let ret = disable_line_if_present(package, &mut lines, file);
-    if let Err(e) = ret {
-        println!("Error: {:?} in file {:?}", e, file);
-        exit(1);
+fn fmt(files: &[PathBuf], check: bool, sort: bool, marker: &str, writer: &mut Writer) -> Result<(), Error> {
+    let mut unformatted = Vec::new();
+    for file in files {
+        let mut deps = DepsFile::load(file)?;
+        format::format_document(&mut deps.lines, sort, marker);
+        if !deps.is_changed() {
+            continue;
+        }
+        if check {
+            unformatted.push(file.clone());
+        } else {
+            println!("Formatting {:?}", file);
+            deps.save(writer)?;
+        }
+    }
+    if unformatted.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Unformatted(unformatted))
     }
 }
 
-fn enable(package: &str, file: &Path) {
-    parse_package(package).expect("package name to be valid");
-    let mut lines = parse_file(file);
-    let ret = enable_existing_line(package, &mut lines, file);
-    if let Err(e) = ret {
-        println!("Error: {:?} in file {:?}", e, file);
-        exit(1);
+/// Runs an edit on each file, or if any flavours are given, on each
+/// flavour's copy of each file. A flavour that fails does not stop the
+/// others; a summary says what happened in each. With `--all-flavours`,
+/// flavours without a copy of a file are skipped rather than given one.
+fn each_file<F>(files: &[PathBuf], flavours: Flavours, config: &Config, writer: &mut Writer, mut edit: F) -> Result<(), Error>
+where F: FnMut(&Path, &mut Writer) -> Result<(), Error> {
+    if flavours.names.is_empty() {
+        return files.iter().try_for_each(|f| edit(f, writer));
+    }
+    let mut summary = Vec::new();
+    let mut failed = Vec::new();
+    let Flavours { names, all } = flavours;
+    for flavour in names {
+        let start = writer.changes.len();
+        let mut missing = Vec::new();
+        let result = files.iter().try_for_each(|f| {
+            let file = config.flavour_file(&flavour, f);
+            if all && !file.exists() {
+                missing.push(format!("no {}", f.file_name().unwrap_or_default().to_string_lossy()));
+                return Ok(());
+            }
+            edit(&file, writer)
+        });
+        let changed: Vec<String> = writer.changes[start..].iter().map(|c| c.path.display().to_string()).collect();
+        let outcome = match &result {
+            Err(e) => format!("failed: {}", e),
+            Ok(()) => {
+                let mut parts = missing;
+                if !changed.is_empty() {
+                    parts.insert(0, format!("changed {}", changed.join(", ")));
+                }
+                if parts.is_empty() { "unchanged".to_string() } else { parts.join(", ") }
+            }
+        };
+        summary.push([flavour.clone(), outcome]);
+        if result.is_err() {
+            failed.push(flavour);
+        }
+    }
+    println!();
+    print_table(&["FLAVOUR", "RESULT"], &summary);
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::FlavoursFailed(failed))
     }
 }
 
-fn delete(package: &str, file: &Path) {
-    let mut lines = parse_file(file);
-    let mut refs = get_refs(&lines, &package);
-    refs.reverse();
-    for r in refs {
-        println!("Deleting package {} from file {:?}", &package, file);
-        lines.remove(r);
+/// Where a file given on the command line is, if it exists: relative to
+/// the current directory, or failing that to the project. With flavours,
+/// files are relative to each flavour, and found if any of them has it.
+fn locate(file: &Path, flavours: &[String], config: &Config) -> Option<PathBuf> {
+    if !flavours.is_empty() {
+        let found = flavours.iter().any(|flavour| config.flavour_file(flavour, file).exists());
+        return if found { Some(file.to_owned()) } else { None };
+    }
+    if file.exists() {
+        return Some(file.to_owned());
+    }
+    Some(under(&config.root, file)).filter(|f| file.is_relative() && f.exists())
+}
+
+/// Files given on the command line, each where `locate` finds it, or as
+/// given if it does not exist.
+fn locate_all(files: Vec<PathBuf>, flavours: &[String], config: &Config) -> Vec<PathBuf> {
+    files.into_iter().map(|f| locate(&f, flavours, config).unwrap_or(f)).collect()
+}
+
+/// The files given on the command line, or else those configured for
+/// the command, or else all the project's deps files.
+fn files_or_defaults(files: &[PathBuf], configured: &[PathBuf], config: &Config) -> Vec<PathBuf> {
+    if !files.is_empty() {
+        files.to_vec()
+    } else if !configured.is_empty() {
+        configured.to_vec()
+    } else {
+        config.all_files()
+    }
+}
+
+/// The files given on the command line, or else those configured for
+/// the command. Commands that change files have no fallback.
+fn files_or_configured(files: Vec<PathBuf>, configured: &[PathBuf], command: &str) -> Result<Vec<PathBuf>, Error> {
+    match (files.is_empty(), configured.is_empty()) {
+        (false, _) => Ok(files),
+        (true, false) => Ok(configured.to_vec()),
+        (true, true) => Err(Error::NoFiles(command.to_string())),
+    }
+}
+
+fn print_entries(entries: &[Entry]) {
+    let rows: Vec<[String; 4]> = entries.iter().map(|e| [
+        e.package.clone(),
+        e.spec.clone(),
+        if e.enabled { "enabled" } else { "disabled" }.to_string(),
+        format!("{}:{}", e.file.display(), e.line),
+    ]).collect();
+    print_table(&["PACKAGE", "SPEC", "STATE", "FILE"], &rows);
+}
+
+/// Prints rows in columns wide enough for their contents.
+fn print_table<R: AsRef<[String]>>(header: &[&str], rows: &[R]) {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row.as_ref()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let print_row = |cells: Vec<&str>| {
+        let last = cells.len() - 1;
+        let line: Vec<String> = cells.iter().enumerate().map(|(i, cell)| {
+            if i == last { cell.to_string() } else { format!("{:width$}", cell, width = widths[i]) }
+        }).collect();
+        println!("{}", line.join("  "));
+    };
+    print_row(header.to_vec());
+    for row in rows {
+        print_row(row.as_ref().iter().map(|c| c.as_str()).collect());
     }
-    write_file(&lines, file);
 }
 
+/// If the source of a deps file is known, checks `version` is a valid
+/// spec for it.
+fn check_spec(version: &str, source: Option<Source>) -> Result<(), Error> {
+    match source {
+        Some(source) => DepSpec::parse(source, version).check(),
+        None => Ok(()),
+    }
+}
@@ -1,10 +1,29 @@
 use std::path::PathBuf;
 use structopt::StructOpt;
 use crate::*;
+use crate::config::Config;
+use crate::project::{Project, Source};
 
 #[derive(Debug, StructOpt)]
 #[structopt(name="messflow")]
-pub enum Opt {
+pub struct Opt {
+    /// Keep the previous version of each changed deps file as `<file>.orig`
+    #[structopt(long, global=true)]
+    pub backup: bool,
+    /// Don't write anything, print a diff of each file that would change
+    /// and exit with status 2 if there are any
+    #[structopt(long="dry-run", global=true)]
+    pub dry_run: bool,
+    /// Work on the project in PROJECT instead of the one the current
+    /// directory is in
+    #[structopt(short="C", long="project", name="PROJECT", global=true, parse(from_os_str))]
+    pub project: Option<PathBuf>,
+    #[structopt(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
     /// Fork a dependency locally from git.
     ///
     /// Repo and branch are optional. If not provided we will attempt
@@ -18,7 +37,8 @@ pub enum Opt {
         repo: Option<String>,
         #[structopt(long="branch", short="b", name="BRANCH")]
         branch: Option<String>,
-        /// Directory forks are being kept in, defaults to "forks"
+        /// Directory forks are being kept in, relative to the current
+        /// directory. Defaults to "forks" in the project or as configured
         #[structopt(long="forks", short="f", name="DIR", parse(from_os_str))]
         forks_dir: Option<PathBuf>,
     },
@@ -35,32 +55,71 @@ pub enum Opt {
     },
 
     /// Stop using a fork of a library 
+    ///
+    /// Goes back to git unless `return_source` in `.messctl.toml` says
+    /// otherwise.
     #[structopt(alias="ret")]
     Return {
         #[structopt(name="PACKAGE")]
         package: String,
-        /// If set, updates to use hex, if not, git.
+        /// Go back to using hex
         #[structopt(long, short)]
         hex: bool,
+        /// Go back to using git
+        #[structopt(long, short, conflicts_with="hex")]
+        git: bool,
     }
 
 }
 
-pub fn run() {
-    run_opt(Opt::from_args());
+pub fn run() -> Result<(), Error> {
+    run_opt(Opt::from_args())
 }
 
-pub fn run_opt(opt: Opt) {
-    use Opt::*;
-    match opt {
+pub fn run_opt(opt: Opt) -> Result<(), Error> {
+    use Command::*;
+    let config = Config::discover(opt.project.as_deref())?;
+    let mut writer = Writer::new(WriteOptions {
+        backup: opt.backup,
+        dry_run: opt.dry_run,
+        journal: Some(config.journal()),
+    });
+    let mut project = Project::load(&config)?;
+    // every edit happens in memory first, so nothing is touched unless
+    // they all succeed
+    let mut clone = None;
+    match opt.command {
         Fork { package, repo, branch, forks_dir } => {
-            fork(&package, repo, branch, forks_dir);
+            let forks_dir = match forks_dir {
+                Some(dir) => project.relative_to_root(&dir)?,
+                None => config.forks.clone(),
+            };
+            clone = Some(project.fork(&package, repo.as_deref(), branch.as_deref(), &forks_dir)?);
         }
         Borrow { package, path } => {
-            borrow(&package, &path);
+            project.borrow(&package, &path)?;
+        }
+        Return { package, hex, git } => {
+            let source = if hex { Source::Hex } else if git { Source::Git } else { config.return_source };
+            project.return_to(&package, source)?;
+        }
+    }
+    match clone {
+        Some(clone) if writer.options.dry_run => {
+            println!("Would clone {} into {:?}", clone.url, clone.dir());
+            project.save(&mut writer)?;
+        }
+        Some(clone) => {
+            let created_forks_dir = !clone.forks_dir.exists();
+            clone.run()?;
+            if let Err(e) = project.save(&mut writer) {
+                clone.remove(created_forks_dir);
+                return Err(e);
+            }
         }
-        Return { package, hex } => {
-            return_(&package, hex);
+        None => {
+            project.save(&mut writer)?;
         }
     }
+    writer.finish()
 }
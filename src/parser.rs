@@ -1,53 +1,349 @@
 use nom::{
     IResult,
     branch::alt,
-    bytes::complete::{tag, take_while},
-    character::complete::{anychar, space0, none_of},
-    combinator::recognize,
+    bytes::complete::{tag, take_while1},
+    character::complete::{char, space0, not_line_ending},
+    combinator::{recognize, verify},
+    error::ErrorKind as NomErrorKind,
     error::VerboseError,
-    sequence::preceded,
-    multi::{many1_count, many_till},
+    sequence::{preceded, tuple},
+    multi::many1_count,
 };
 use std::fs::File;
-use std::io::Read;
+use std::io::{ErrorKind, Read};
 use std::path::Path;
+use crate::diagnostics::Diagnostic;
 use crate::types::*;
 
 fn alnum_(c: char) -> bool {
     (c == '_') || c.is_ascii_lowercase() || c.is_ascii_digit()
 }
 
+/// Why `name` is not a valid package name, if it is not.
+///
+/// Package names follow the rules for Mix application names: a
+/// lowercase letter, then lowercase letters, digits and underscores.
+fn name_problem(name: &str) -> Option<String> {
+    match name.chars().next() {
+        None => Some("package names may not be empty".to_string()),
+        Some(c) if !c.is_ascii_lowercase() =>
+            Some(format!("package names must start with a lowercase letter, not `{}`", c)),
+        _ => name.chars().find(|c| !alnum_(*c)).map(|c| {
+            format!("invalid character `{}`, package names may only contain a-z, 0-9 and _", c)
+        }),
+    }
+}
+
+/// Checks that a package name given to us is one mess could use.
+pub fn validate_package_name(name: &str) -> Result<(), Error> {
+    match name_problem(name) {
+        None => Ok(()),
+        Some(reason) => Err(Error::InvalidPackageName { name: name.to_owned(), reason }),
+    }
+}
+
+fn parse_name(input: &str) -> IResult<&str, &str> {
+    verify(take_while1(alnum_), |name: &str| name_problem(name).is_none())(input)
+}
+
+/// Reads a quoted, non-empty value off the front of `input`. Inside
+/// the quotes, `\\` and a backslash before the quote character are
+/// the only escapes.
+///
+/// On failure, returns the offset into `input` of the problem and a
+/// description of it.
+fn scan_value(input: &str) -> Result<(&str, Quote, String), (usize, String)> {
+    let quote = match input.chars().next() {
+        Some('"') => Quote::Double,
+        Some('\'') => Quote::Single,
+        _ => return Err((0, "expected a quoted value".to_string())),
+    };
+    let q = quote.char();
+    let mut value = String::new();
+    let mut chars = input.char_indices().skip(1);
+    while let Some((at, c)) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some((_, e)) if e == '\\' || e == q => value.push(e),
+                Some((_, e)) => return Err((at, format!("unknown escape `\\{}`", e))),
+                None => break,
+            }
+        } else if c == q {
+            if value.is_empty() {
+                return Err((0, "empty value".to_string()));
+            }
+            return Ok((&input[at + 1..], quote, value));
+        } else {
+            value.push(c);
+        }
+    }
+    Err((0, "unterminated string".to_string()))
+}
+
+fn parse_value(input: &str) -> IResult<&str, (Quote, String)> {
+    scan_value(input)
+        .map(|(rest, quote, value)| (rest, (quote, value)))
+        .map_err(|(at, _)| nom::Err::Error((&input[at..], NomErrorKind::Verify)))
+}
+
 pub fn parse_package(input: &str) -> IResult<&str, Package> {
-    let (input, package) = take_while(alnum_)(input)?;
-    let (input, _) = preceded(space0, preceded(tag("="), space0))(input)?;
-    let (input, value) = preceded(tag("\""), recognize(many1_count(none_of("\""))))(input)?;
-    let (post, _) = tag("\"")(input)?;
-    Ok((post, Package::new(package, value)))
+    let (input, name) = parse_name(input)?;
+    let (input, eq) = recognize(tuple((space0, tag("="), space0)))(input)?;
+    let (post, (quote, value)) = parse_value(input)?;
+    let mut package = Package::new(name, &value);
+    package.eq = eq.to_owned();
+    package.quote = quote;
+    Ok((post, package))
+}
+
+/// Succeeds only at the end of the input.
+fn eof(input: &str) -> IResult<&str, &str> {
+    if input.is_empty() {
+        Ok((input, input))
+    } else {
+        Err(nom::Err::Error((input, NomErrorKind::Eof)))
+    }
+}
+
+/// What may follow a package on its line: whitespace, then either
+/// nothing or a comment.
+fn parse_post(input: &str) -> IResult<&str, &str> {
+    recognize(tuple((space0, alt((eof, preceded(char('#'), not_line_ending))))))(input)
+}
+
+/// A comment marker: any indentation, one or more `#`, then spaces.
+fn parse_marker(input: &str) -> IResult<&str, &str> {
+    recognize(tuple((space0, many1_count(char('#')), space0)))(input)
 }
 
 fn parse_enabled(input: &str) -> IResult<&str, Line> {
     let (input, pre) = space0::<&str, VerboseError<&str>>(input).unwrap();
-    let (post, package) = parse_package(input)?;
+    let (input, package) = parse_package(input)?;
+    let (_, post) = parse_post(input)?;
     Ok(("", Line::Enabled(Enabled::new(pre, post, package))))
 }
 
 fn parse_disabled(input: &str) -> IResult<&str, Line> {
-    let (post, (pre, package)) = many_till(anychar, parse_package)(input)?;
-    Ok(("", Line::Disabled(Disabled::new(pre.iter().collect(), post, package))))
+    let (input, pre) = parse_marker(input)?;
+    let (input, package) = parse_package(input)?;
+    let (_, post) = parse_post(input)?;
+    Ok(("", Line::Disabled(Disabled::new(pre.to_owned(), post, package))))
+}
+
+fn parse_blank(input: &str) -> IResult<&str, Line> {
+    let (_, text) = recognize(tuple((space0, eof)))(input)?;
+    Ok(("", Line::Blank(Blank { number: 0, text: text.to_owned(), eol: Eol::Lf })))
+}
+
+fn parse_comment(input: &str) -> IResult<&str, Line> {
+    let (text, marker) = parse_marker(input)?;
+    let comment = Comment { number: 0, marker: marker.to_owned(), text: text.to_owned(), eol: Eol::Lf };
+    Ok(("", Line::Comment(comment)))
+}
+
+fn space(c: char) -> bool {
+    c == ' ' || c == '\t'
+}
+
+/// Works out where and why a line failed to parse as a dependency.
+fn diagnose(input: &str) -> (usize, String) {
+    let at = |rest: &str| input.len() - rest.len();
+    let rest = input.trim_start_matches(space);
+    let name = rest.find(|c| !alnum_(c)).unwrap_or(rest.len());
+    let after_name = &rest[name..];
+    match after_name.chars().next() {
+        Some('=') if name == 0 => return (at(rest), "expected a package name".to_string()),
+        Some(c) if c != '=' && !space(c) =>
+            return (at(after_name), format!("invalid character `{}` in package name", c)),
+        _ => {}
+    }
+    if let Some(problem) = name_problem(&rest[..name]) {
+        return (at(rest), problem);
+    }
+    let eq = after_name.trim_start_matches(space);
+    if !eq.starts_with('=') {
+        return (at(eq), "expected `=`".to_string());
+    }
+    let value = eq[1..].trim_start_matches(space);
+    match scan_value(value) {
+        Err((offset, message)) => (at(value) + offset, message),
+        Ok((post, _, _)) => (at(post.trim_start_matches(space)), "unexpected text after value".to_string()),
+    }
 }
 
-fn parse_ignored(input: &str) -> IResult<&str, Line> {
-    Ok(("", Line::Ignored(input.to_owned())))
+/// Keeps a line we could not parse, noting where and why.
+fn malformed(input: &str) -> Line {
+    let (column, message) = diagnose(input);
+    Line::Malformed(Malformed { number: 0, text: input.to_owned(), column, message, eol: Eol::Lf })
 }
 
-fn parse_line(input: &str) -> Line {
-    alt((parse_enabled, parse_disabled, parse_ignored))(input).unwrap().1
+/// Parses one line. Anything that is not a dependency, a disabled
+/// dependency, a comment or blank is malformed.
+pub fn parse_line(input: &str) -> Line {
+    alt((parse_enabled, parse_disabled, parse_blank, parse_comment))(input)
+        .map(|(_, line)| line)
+        .unwrap_or_else(|_| malformed(input))
 }
 
-pub fn parse_file(path: &Path) -> Vec<Line> {
-    if let Ok(mut file) = File::open(path) {
-        let mut contents = String::new();
-        file.read_to_string(&mut contents).unwrap();
-        contents.lines().map(parse_line).collect()
-    } else { Vec::new() }
+/// Splits text into lines, remembering how each one was terminated.
+fn split_lines(text: &str) -> impl Iterator<Item = (&str, Eol)> {
+    text.split_inclusive('\n').map(|line| {
+        if let Some(line) = line.strip_suffix("\r\n") {
+            (line, Eol::CrLf)
+        } else if let Some(line) = line.strip_suffix('\n') {
+            (line, Eol::Lf)
+        } else {
+            (line, Eol::None)
+        }
+    })
+}
+
+/// Parses the contents of a deps file into lines. Never fails: lines
+/// that do not parse come back as `Line::Malformed`.
+pub fn parse_text(text: &str) -> Document {
+    let (bom, text) = match text.strip_prefix('\u{feff}') {
+        Some(text) => (true, text),
+        None => (false, text),
+    };
+    let lines = split_lines(text).enumerate().map(|(index, (line, eol))| {
+        let mut line = parse_line(line);
+        line.set_number(index + 1);
+        line.set_eol(eol);
+        line
+    }).collect();
+    Document::new(bom, lines)
+}
+
+/// Parses the contents of a deps file, failing on the first malformed
+/// line. `path` is only used in errors.
+pub fn parse_document(text: &str, path: &Path) -> Result<Document, Error> {
+    let document = parse_text(text);
+    match document.malformed() {
+        Some(m) => Err(Error::Parse(Diagnostic::new(path, m.number, m.column, &m.message, &m.text))),
+        None => Ok(document),
+    }
+}
+
+/// Reads a deps file. Returns `None` if it does not exist.
+///
+/// A file that exists but cannot be read is an IO error, and one that
+/// is not valid UTF-8 is a parse error pointing at the first bad byte.
+pub fn read_file(path: &Path) -> Result<Option<String>, Error> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(Error::io(path, e)),
+    };
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes).map_err(|e| Error::io(path, e))?;
+    String::from_utf8(bytes).map(Some).map_err(|e| {
+        let bytes = e.as_bytes();
+        let valid = e.utf8_error().valid_up_to();
+        let start = bytes[..valid].iter().rposition(|b| *b == b'\n').map(|i| i + 1).unwrap_or(0);
+        let end = bytes[valid..].iter().position(|b| *b == b'\n').map(|i| valid + i).unwrap_or(bytes.len());
+        let line = bytes[..valid].iter().filter(|b| **b == b'\n').count() + 1;
+        let text = String::from_utf8_lossy(&bytes[start..end]);
+        Error::Parse(Diagnostic::new(path, line, valid - start, "invalid UTF-8", text.trim_end_matches('\r')))
+    })
+}
+
+/// Parses a deps file. A file that does not exist parses as empty.
+pub fn parse_file(path: &Path) -> Result<Document, Error> {
+    match read_file(path)? {
+        Some(contents) => parse_document(&contents, path),
+        None => Ok(Document::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Document {
+        parse_document(text, Path::new("deps.hex")).unwrap()
+    }
+
+    fn round_trip(text: &str) {
+        assert_eq!(parse(text).to_string(), text);
+    }
+
+    fn enabled(name: &str, version: &str) -> Line {
+        Line::Enabled(Enabled::new("", "", Package::new(name, version)))
+    }
+
+    #[test]
+    fn quoted_values_read_back_as_written() {
+        let values = [r#"say "hi""#, "it's", r"back\slash", r#"all "'\ of them"#, r"trailing\"];
+        for quote in [Quote::Double, Quote::Single] {
+            for value in values {
+                let text = format!("foo = {}", quote.quote(value));
+                match parse_line(&text) {
+                    Line::Enabled(e) => {
+                        assert_eq!(e.package.version, value, "{}", text);
+                        assert_eq!(e.package.quote, quote, "{}", text);
+                        assert_eq!(Line::Enabled(e).to_string(), text);
+                    }
+                    line => panic!("{:?} parsed as {:?}", text, line),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn other_quote_needs_no_escape() {
+        let (rest, quote, value) = scan_value(r#"'say "hi"' # comment"#).unwrap();
+        assert_eq!((rest, quote, value.as_str()), (" # comment", Quote::Single, r#"say "hi""#));
+        let (_, _, value) = scan_value(r#""it's""#).unwrap();
+        assert_eq!(value, "it's");
+    }
+
+    #[test]
+    fn bad_values_are_rejected() {
+        assert_eq!(scan_value(r#""a\nb""#).unwrap_err().1, r"unknown escape `\n`");
+        assert_eq!(scan_value(r"'a\'").unwrap_err().1, "unterminated string");
+        assert_eq!(scan_value(r#""""#).unwrap_err().1, "empty value");
+        assert_eq!(scan_value("1.0.0").unwrap_err().1, "expected a quoted value");
+    }
+
+    #[test]
+    fn escaped_and_malformed_lines_round_trip() {
+        let text = "foo = 'it\\'s'\r\nbar = \"a\\\\b\"\nnot a dependency\r\n  \t\n#";
+        assert_eq!(parse_text(text).to_string(), text);
+    }
+
+    #[test]
+    fn files_round_trip_byte_for_byte() {
+        round_trip("");
+        round_trip("foo = \"1.0.0\"\n");
+        round_trip("foo = \"1.0.0\"\r\nbar = \"2.0.0\"\r\n");
+        round_trip("foo = \"1.0.0\"\r\n# bar = \"2.0.0\"\n\n  baz=\"3\" # why\r\n");
+        round_trip("\u{feff}foo = \"1.0.0\"\n");
+        round_trip("foo = \"1.0.0\"\nbar = \"2.0.0\"");
+    }
+
+    #[test]
+    fn push_keeps_line_endings() {
+        let mut document = parse("foo = \"1\"\r\n");
+        document.push(enabled("bar", "2"));
+        assert_eq!(document.to_string(), "foo = \"1\"\r\nbar = \"2\"\r\n");
+
+        let mut document = parse("\u{feff}foo = \"1\"");
+        document.push(enabled("bar", "2"));
+        assert_eq!(document.to_string(), "\u{feff}foo = \"1\"\nbar = \"2\"");
+
+        let mut document = Document::default();
+        document.push(enabled("foo", "1"));
+        assert_eq!(document.to_string(), "foo = \"1\"\n");
+    }
+
+    #[test]
+    fn remove_keeps_missing_trailing_newline() {
+        let mut document = parse("foo = \"1\"\r\nbar = \"2\"");
+        document.remove(1);
+        assert_eq!(document.to_string(), "foo = \"1\"");
+
+        let mut document = parse("foo = \"1\"\r\nbar = \"2\"\r\n");
+        document.remove(0);
+        assert_eq!(document.to_string(), "bar = \"2\"\r\n");
+    }
 }
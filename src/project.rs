@@ -0,0 +1,444 @@
+use std::fs::{self, create_dir_all, metadata};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use crate::*;
+use crate::config::{under, Config, DEFAULT_MARKER};
+use crate::diagnostics::did_you_mean;
+pub use crate::types::Source;
+use serde::Serialize;
+
+/// A package's entry in a deps file, for reporting.
+#[derive(Clone, Debug, Serialize)]
+pub struct Entry {
+    pub package: String,
+    pub spec: String,
+    pub enabled: bool,
+    /// Which source the file is for, if it is a standard deps file.
+    pub source: Option<Source>,
+    pub file: PathBuf,
+    pub line: usize,
+}
+
+/// A deps file held in memory, remembering what it looked like on disk.
+#[derive(Clone, Debug)]
+pub struct DepsFile {
+    pub path: PathBuf,
+    /// The source the file is for, if known.
+    pub source: Option<Source>,
+    pub lines: Document,
+    original: Option<String>,
+}
+
+impl DepsFile {
+    /// Loads a deps file, judging its source by its name. A file that
+    /// does not exist loads as empty.
+    pub fn load(path: impl Into<PathBuf>) -> Result<DepsFile, Error> {
+        let path = path.into();
+        let original = read_file(&path)?;
+        let lines = match &original {
+            Some(text) => parse_document(text, &path)?,
+            None => Document::default(),
+        };
+        Ok(DepsFile { source: Source::of_file(&path), path, lines, original })
+    }
+
+    /// Whether the file existed when it was loaded.
+    pub fn exists(&self) -> bool {
+        self.original.is_some()
+    }
+
+    /// Finds the single line for a package, if there is one.
+    pub fn find(&self, package: &str) -> Result<Option<(&Line, usize)>, Error> {
+        match find_line(&self.lines, package) {
+            Ok(found) => Ok(Some(found)),
+            Err(FindError::Missing) => Ok(None),
+            Err(e) => Err(Error::find(package, &self.path, e)),
+        }
+    }
+
+    /// Like `find`, but a missing package is an error.
+    pub fn get(&self, package: &str) -> Result<(&Line, usize), Error> {
+        match self.find(package)? {
+            Some(found) => Ok(found),
+            None if !self.exists() => Err(Error::NoSuchFile(self.path.clone())),
+            None => Err(Error::find(package, &self.path, FindError::Missing)
+                .suggesting(self.suggest(package))),
+        }
+    }
+
+    /// A package in this file with a name close to `package`.
+    pub fn suggest(&self, package: &str) -> Option<String> {
+        did_you_mean(package, self.lines.iter().filter_map(|l| l.package()).map(|p| p.name.as_str()))
+    }
+
+    /// Every package in the file, enabled or not, in file order.
+    pub fn entries(&self) -> Vec<Entry> {
+        let source = self.source;
+        self.lines.iter().filter_map(|line| {
+            let package = line.package()?;
+            Some(Entry {
+                package: package.name.clone(),
+                spec: package.version.clone(),
+                enabled: matches!(line, Line::Enabled(_)),
+                source,
+                file: self.path.clone(),
+                line: line.number(),
+            })
+        }).collect()
+    }
+
+    /// Whether the lines differ from what was loaded.
+    pub fn is_changed(&self) -> bool {
+        match &self.original {
+            Some(original) => *original != self.lines.to_string(),
+            None => !self.lines.is_empty(),
+        }
+    }
+
+    /// Writes the file if it has changed. Returns whether it did.
+    pub fn save(&mut self, writer: &mut Writer) -> Result<bool, Error> {
+        if !self.is_changed() {
+            return Ok(false);
+        }
+        writer.write(&self.path, self.original.as_deref(), &self.lines)?;
+        self.original = Some(self.lines.to_string());
+        Ok(true)
+    }
+
+    /// Runs one of the edits in `lines` against this file.
+    pub fn edit<F>(&mut self, package: &str, f: F) -> Result<(), Error>
+    where F: FnOnce(&mut Document, &Path) -> Result<(), ChangeError> {
+        f(&mut self.lines, &self.path).map_err(|e| match e {
+            ChangeError::Missing if !self.exists() => Error::NoSuchFile(self.path.clone()),
+            ChangeError::Missing => {
+                Error::change(package, &self.path, e).suggesting(self.suggest(package))
+            }
+            e => Error::change(package, &self.path, e),
+        })
+    }
+}
+
+/// The deps files of a mess project, loaded once and edited together.
+///
+/// Nothing touches the disk until `save`, which only writes the files
+/// that actually changed.
+#[derive(Clone, Debug)]
+pub struct Project {
+    hex: DepsFile,
+    git: DepsFile,
+    path: DepsFile,
+    /// The project directory, which paths in `deps.path` are relative to.
+    root: PathBuf,
+    /// How dependencies get disabled.
+    marker: String,
+}
+
+impl Project {
+    /// Loads the deps files where the config says they are.
+    pub fn load(config: &Config) -> Result<Project, Error> {
+        let file = |source: Source| config.file(source).to_owned();
+        let mut project = Project::from_files(file(Source::Hex), file(Source::Git), file(Source::Path))?;
+        project.root = config.root.clone();
+        project.marker = config.comment_marker.clone();
+        Ok(project)
+    }
+
+    /// Loads a project from explicitly named deps files.
+    pub fn from_files(hex: PathBuf, git: PathBuf, path: PathBuf) -> Result<Project, Error> {
+        // whatever the files are called, each is for its own source
+        let load = |path, source| DepsFile::load(path).map(|deps| DepsFile { source: Some(source), ..deps });
+        Ok(Project {
+            hex: load(hex, Source::Hex)?,
+            git: load(git, Source::Git)?,
+            path: load(path, Source::Path)?,
+            root: PathBuf::from("."),
+            marker: DEFAULT_MARKER.to_string(),
+        })
+    }
+
+    pub fn hex(&self) -> &DepsFile { &self.hex }
+    pub fn git(&self) -> &DepsFile { &self.git }
+    pub fn path(&self) -> &DepsFile { &self.path }
+
+    pub fn file(&self, source: Source) -> &DepsFile {
+        match source {
+            Source::Hex => &self.hex,
+            Source::Git => &self.git,
+            Source::Path => &self.path,
+        }
+    }
+
+    pub fn file_mut(&mut self, source: Source) -> &mut DepsFile {
+        match source {
+            Source::Hex => &mut self.hex,
+            Source::Git => &mut self.git,
+            Source::Path => &mut self.path,
+        }
+    }
+
+    /// The source mess will use for a package: the enabled entry with
+    /// the highest precedence, if any.
+    pub fn effective_source(&self, package: &str) -> Result<Option<Source>, Error> {
+        let mut effective = None;
+        for source in Source::ALL.iter().copied() {
+            if let Some((Line::Enabled(_), _)) = self.file(source).find(package)? {
+                effective = Some(source);
+            }
+        }
+        Ok(effective)
+    }
+
+    /// Every entry for a package across the deps files, highest
+    /// precedence first.
+    pub fn occurrences(&self, package: &str) -> Vec<Entry> {
+        Source::ALL.iter().rev()
+            .flat_map(|s| self.file(*s).entries())
+            .filter(|e| e.package == package)
+            .collect()
+    }
+
+    /// A package in any of the deps files with a name close to `package`.
+    pub fn suggest(&self, package: &str) -> Option<String> {
+        let names: Vec<String> = Source::ALL.iter()
+            .flat_map(|s| self.file(*s).entries())
+            .map(|e| e.package)
+            .collect();
+        did_you_mean(package, names.iter().map(|n| n.as_str()))
+    }
+
+    /// Switches a package to a clone of its git repo in the forks
+    /// directory, which is relative to the project. Returns the clone to
+    /// make, which is left to the caller so it can be skipped or done
+    /// alongside saving.
+    ///
+    /// Repo and branch default to the package's entry in `deps.git`,
+    /// which is disabled. The dep is also disabled in `deps.hex`.
+    pub fn fork(&mut self, package: &str, repo: Option<&str>, branch: Option<&str>, forks_dir: &Path) -> Result<GitClone, Error> {
+        let marker = self.marker.clone();
+        validate_package_name(package)?;
+        // find the repo and ref if not provided
+        let spec = match repo {
+            Some(repo) => repo.to_string(),
+            None => self.git_spec_and_disable(package)?,
+        };
+        let (url, git_ref) = match DepSpec::parse(Source::Git, &spec) {
+            DepSpec::Git { url, ref_kind } => (url, ref_kind),
+            _ => unreachable!(),
+        };
+        let git_ref = branch.map(|b| GitRef::Branch(b.to_string())).or(git_ref);
+        let dir = forks_dir.join(package);
+        let clone = GitClone { url, git_ref, forks_dir: under(&self.root, forks_dir), package: package.to_owned() };
+        if fs::symlink_metadata(clone.dir()).is_ok() {
+            return Err(Error::AlreadyExists(clone.dir()));
+        }
+        let value = path_value(&dir)?;
+        self.path.edit(package, |lines, path| add_or_update_line(package, value, lines, path))?;
+        self.hex.edit(package, |lines, path| disable_line_if_present(package, &marker, lines, path))?;
+        Ok(clone)
+    }
+
+    fn git_spec_and_disable(&mut self, package: &str) -> Result<String, Error> {
+        let marker = self.marker.clone();
+        let spec = match self.git.get(package)? {
+            (Line::Enabled(e), _) => e.package.version.clone(),
+            (Line::Disabled(d), _) => d.package.version.clone(),
+            _ => unreachable!(),
+        };
+        self.git.edit(package, |lines, path| disable_line_if_present(package, &marker, lines, path))?;
+        Ok(spec)
+    }
+
+    /// Uses an existing local copy of a package, disabling it in
+    /// `deps.hex` and `deps.git`. `dir` is relative to the current
+    /// directory.
+    pub fn borrow(&mut self, package: &str, dir: &Path) -> Result<(), Error> {
+        let marker = self.marker.clone();
+        validate_package_name(package)?;
+        let meta = metadata(dir).map_err(|e| Error::io(dir, e))?;
+        if !meta.is_dir() {
+            return Err(Error::NotADirectory(dir.to_owned()));
+        }
+        let dir = self.relative_to_root(dir)?;
+        let value = path_value(&dir)?;
+        self.path.edit(package, |lines, path| add_or_update_line(package, value, lines, path))?;
+        self.hex.edit(package, |lines, path| disable_line_if_present(package, &marker, lines, path))?;
+        self.git.edit(package, |lines, path| disable_line_if_present(package, &marker, lines, path))
+    }
+
+    /// A path relative to the current directory, as given on the command
+    /// line, relative to the project instead if it is inside it, otherwise
+    /// absolute. The path need not exist yet.
+    pub fn relative_to_root(&self, dir: &Path) -> Result<PathBuf, Error> {
+        if self.root == Path::new(".") {
+            return Ok(dir.to_owned());
+        }
+        let canonical = |p: &Path| fs::canonicalize(p).map_err(|e| Error::io(p, e));
+        let cwd = std::env::current_dir().map_err(|e| Error::io(Path::new("."), e))?;
+        let dir = cwd.join(dir);
+        // only the part that exists can be canonicalized
+        let existing = dir.ancestors().find(|a| a.exists()).unwrap_or(&cwd);
+        let dir = canonical(existing)?.join(dir.strip_prefix(existing).unwrap());
+        let root = canonical(&self.root)?;
+        Ok(dir.strip_prefix(&root).map(|d| d.to_owned()).unwrap_or(dir))
+    }
+
+    /// Switches a package to one source: enables its existing entry in
+    /// that source's deps file and disables it in the others. Fails
+    /// without changing anything if that file has no entry for it.
+    pub fn return_to(&mut self, package: &str, source: Source) -> Result<(), Error> {
+        let marker = self.marker.clone();
+        validate_package_name(package)?;
+        self.file_mut(source)
+            .edit(package, |lines, path| enable_existing_line(package, lines, path))?;
+        for other in Source::ALL.iter().copied().filter(|s| *s != source) {
+            self.file_mut(other)
+                .edit(package, |lines, path| disable_line_if_present(package, &marker, lines, path))?;
+        }
+        Ok(())
+    }
+
+    /// Moves a package's entry to another source with a new spec,
+    /// keeping its trailing comment. The old entry is disabled, or
+    /// removed if `remove` is set.
+    ///
+    /// Moves from the package's effective source unless `from` is given,
+    /// or if it is disabled everywhere, from the source with the highest
+    /// precedence that has it.
+    pub fn move_to(&mut self, package: &str, from: Option<Source>, to: Source, spec: &str, remove: bool) -> Result<(), Error> {
+        validate_package_name(package)?;
+        check_value(spec)?;
+        DepSpec::parse(to, spec).check()?;
+        let from = match from {
+            Some(from) => from,
+            None => self.source_with(package)?,
+        };
+        if from == to {
+            return Err(Error::SameSource { package: package.to_owned(), path: self.file(to).path.clone() });
+        }
+        let (line, index) = self.file(from).get(package)?;
+        let post = match line {
+            Line::Enabled(e) => e.post.clone(),
+            Line::Disabled(d) => d.post.clone(),
+            _ => unreachable!(),
+        };
+        self.file_mut(to).edit(package, |lines, path| add_or_replace_line(package, spec, &post, lines, path))?;
+        let marker = self.marker.clone();
+        let old = self.file_mut(from);
+        if remove {
+            println!("Deleting package {} from file {:?}", package, old.path);
+            old.lines.remove(index);
+            Ok(())
+        } else {
+            old.edit(package, |lines, path| disable_existing_line(package, &marker, lines, path))
+        }
+    }
+
+    /// The source a package is effectively taken from, or if it is
+    /// disabled everywhere, the highest precedence one that has it.
+    fn source_with(&self, package: &str) -> Result<Source, Error> {
+        if let Some(source) = self.effective_source(package)? {
+            return Ok(source);
+        }
+        for source in Source::ALL.iter().rev().copied() {
+            if self.file(source).find(package)?.is_some() {
+                return Ok(source);
+            }
+        }
+        let error = Error::NotInProject { package: package.to_owned(), suggestion: None };
+        Err(error.suggesting(self.suggest(package)))
+    }
+
+    /// Writes every deps file that changed, or none of them: if one
+    /// cannot be written, those already written are put back. Returns
+    /// the paths written.
+    pub fn save(&mut self, writer: &mut Writer) -> Result<Vec<PathBuf>, Error> {
+        let start = writer.changes.len();
+        let mut saved = Vec::new();
+        for source in Source::ALL.iter().copied() {
+            let file = self.file_mut(source);
+            match file.save(writer) {
+                Ok(true) => saved.push(file.path.clone()),
+                Ok(false) => {}
+                Err(e) => return Err(writer.abort(start, e)),
+            }
+        }
+        Ok(saved)
+    }
+}
+
+/// A git clone of a package into a forks directory, as planned by
+/// `Project::fork`.
+#[derive(Clone, Debug)]
+pub struct GitClone {
+    pub url: String,
+    pub git_ref: Option<GitRef>,
+    pub forks_dir: PathBuf,
+    pub package: String,
+}
+
+impl GitClone {
+    /// Where the clone goes.
+    pub fn dir(&self) -> PathBuf {
+        self.forks_dir.join(&self.package)
+    }
+
+    /// Creates the forks directory if need be and clones into it,
+    /// checking out the ref if there is one. If anything fails, what was
+    /// created is removed again.
+    pub fn run(&self) -> Result<(), Error> {
+        let created_forks_dir = !self.forks_dir.exists();
+        let cloned = self.clone_and_checkout();
+        if cloned.is_err() {
+            self.remove(created_forks_dir);
+        }
+        cloned
+    }
+
+    fn clone_and_checkout(&self) -> Result<(), Error> {
+        let dir = &self.forks_dir;
+        create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+        let mut clone = Command::new("git");
+        clone.arg("-C").arg(dir).arg("clone");
+        if let Some(GitRef::Branch(name)) | Some(GitRef::Tag(name)) = &self.git_ref {
+            clone.arg("-b").arg(name);
+        }
+        git(clone.arg(&self.url).arg(&self.package), "git clone failed.")?;
+        if let Some(GitRef::Commit(sha)) = &self.git_ref {
+            let mut checkout = Command::new("git");
+            checkout.arg("-C").arg(self.dir()).arg("checkout").arg(sha);
+            git(&mut checkout, "git checkout failed.")?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the clone, and the forks directory too if asked and it is
+    /// left empty. `fork` refuses to clone over an existing directory, so
+    /// this only ever removes what `run` made.
+    pub fn remove(&self, forks_dir: bool) {
+        let _ = fs::remove_dir_all(self.dir());
+        if forks_dir {
+            let _ = fs::remove_dir(&self.forks_dir);
+        }
+    }
+}
+
+/// A directory as `deps.path` will have it, which must be valid UTF-8.
+fn path_value(dir: &Path) -> Result<&str, Error> {
+    let value = dir.to_str().ok_or_else(|| Error::InvalidValue {
+        value: dir.to_string_lossy().into_owned(),
+        reason: "paths in deps.path must be valid UTF-8".to_string(),
+    })?;
+    check_value(value)?;
+    Ok(value)
+}
+
+fn git(command: &mut Command, failed: &str) -> Result<(), Error> {
+    let status = command.status().map_err(|e| {
+        Error::Git { message: format!("could not run git: {}", e), code: None }
+    })?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::Git { message: failed.to_string(), code: status.code() })
+    }
+}
@@ -1,6 +1,10 @@
 use std::fmt;
-use std::path::Path;
-use std::process::exit;
+use std::str::FromStr;
+use std::ops::{Deref, DerefMut, Range};
+use std::path::{Path, PathBuf};
+use crate::diagnostics::Diagnostic;
+use crate::version::Requirement;
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug)]
 pub enum FindError {
@@ -8,30 +12,443 @@ pub enum FindError {
     OccursMany,
 }
 
+impl FindError {
+    /// Succeeds if the package was simply not found, fails if it was
+    /// found more than once.
+    pub fn assert_missing(self, package: &str, path: &Path) -> Result<(), Error> {
+        match self {
+            FindError::Missing => Ok(()),
+            error => Err(Error::find(package, path, error)),
+        }
+    }
+}
+
+impl fmt::Display for FindError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FindError::Missing => write!(f, "not found"),
+            FindError::OccursMany => write!(f, "occurs multiple times"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum ChangeError {
     AlreadyExists,
     Missing,
     OccursMany,
-    IO(std::io::Error),
 }
 
-impl FindError {
-    pub fn assert_missing(&self, package: &str, path: &Path) {
-        println!("Error: Package {} occurs multiple times in file {:?}", package, path);
-        exit(1);
+impl fmt::Display for ChangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChangeError::AlreadyExists => write!(f, "already exists"),
+            ChangeError::Missing => write!(f, "not found"),
+            ChangeError::OccursMany => write!(f, "occurs multiple times"),
+        }
+    }
+}
+
+/// Everything that can go wrong in the `mess` crate.
+///
+/// Library code only ever returns these. Turning them into messages
+/// and exit codes is the job of the binaries.
+#[derive(Debug)]
+pub enum Error {
+    /// Looking up a package in a deps file failed. If it was missing,
+    /// `suggestion` may hold a similarly named package that is there.
+    Find { package: String, path: PathBuf, error: FindError, suggestion: Option<String> },
+    /// Changing a package in a deps file failed.
+    Change { package: String, path: PathBuf, error: ChangeError, suggestion: Option<String> },
+    /// A package is not in any of a project's deps files.
+    NotInProject { package: String, suggestion: Option<String> },
+    /// A deps file could not be parsed.
+    Parse(Diagnostic),
+    /// A deps file we needed to read does not exist.
+    NoSuchFile(PathBuf),
+    /// `messctl check` found this many problems.
+    CheckFailed(usize),
+    /// `messctl fmt --check` found these files were not formatted.
+    Unformatted(Vec<PathBuf>),
+    /// A dry run found these files would be changed.
+    WouldChange(Vec<PathBuf>),
+    /// Running git failed. `code` is git's exit code, if it has one.
+    Git { message: String, code: Option<i32> },
+    /// A package name is not one mess could use.
+    InvalidPackageName { name: String, reason: String },
+    /// A value cannot be written to a deps file.
+    InvalidValue { value: String, reason: String },
+    /// There is no flavour directory with this name.
+    UnknownFlavour { name: String, suggestion: Option<String> },
+    /// A command failed in these flavours.
+    FlavoursFailed(Vec<String>),
+    /// None of the flavours in `dir` has any of the deps `files`.
+    NoFlavourFiles { dir: PathBuf, files: Vec<PathBuf> },
+    /// Asked to move a package to the deps file it is already in.
+    SameSource { package: String, path: PathBuf },
+    /// `add` or `update` was given the same package more than once.
+    DuplicateSpec(String),
+    /// A file to edit does not exist and was not given after `--`,
+    /// which is how new files are asked for.
+    NewFileNotAfterSeparator(PathBuf),
+    /// `add` or `update` was not given any packages.
+    NoSpecs,
+    /// A command that needs deps files was given none and has no
+    /// default configured.
+    NoFiles(String),
+    /// Asked to undo more commands than the journal has.
+    NothingToUndo { requested: usize, available: usize },
+    /// A file was changed by something else after a command wrote it,
+    /// so undoing the command would lose that change.
+    ChangedSince(PathBuf),
+    /// Something is in the way of a file or directory we would create.
+    AlreadyExists(PathBuf),
+    /// A path we expected to be a directory is not one.
+    NotADirectory(PathBuf),
+    /// Reading or writing a file failed.
+    IO { path: PathBuf, error: std::io::Error },
+    /// A command failed with `error` part way through writing, then
+    /// putting back the files it had already written failed too.
+    RollbackFailed { error: Box<Error>, rollback: Box<Error> },
+}
+
+impl Error {
+    pub fn find(package: &str, path: &Path, error: FindError) -> Error {
+        Error::Find { package: package.to_owned(), path: path.to_owned(), error, suggestion: None }
+    }
+
+    pub fn change(package: &str, path: &Path, error: ChangeError) -> Error {
+        Error::Change { package: package.to_owned(), path: path.to_owned(), error, suggestion: None }
+    }
+
+    /// Adds a "did you mean" suggestion to a lookup or change error.
+    pub fn suggesting(mut self, name: Option<String>) -> Error {
+        match &mut self {
+            Error::Find { suggestion, .. } | Error::Change { suggestion, .. } |
+            Error::NotInProject { suggestion, .. } |
+            Error::UnknownFlavour { suggestion, .. } => *suggestion = name,
+            _ => {}
+        }
+        self
+    }
+
+    pub fn io(path: &Path, error: std::io::Error) -> Error {
+        Error::IO { path: path.to_owned(), error }
+    }
+
+    /// The exit code a binary should use when failing with this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Git { code: Some(code), .. } => *code,
+            Error::WouldChange(_) => 2,
+            Error::RollbackFailed { error, .. } => error.exit_code(),
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Find { package, path, error, suggestion } => {
+                write!(f, "Package {} {} in file {:?}", package, error, path)?;
+                did_you_mean(f, suggestion)
+            }
+            Error::Change { package, path, error, suggestion } => {
+                write!(f, "Package {} {} in file {:?}", package, error, path)?;
+                did_you_mean(f, suggestion)
+            }
+            Error::NotInProject { package, suggestion } => {
+                write!(f, "Package {} not found in any deps file", package)?;
+                did_you_mean(f, suggestion)
+            }
+            Error::Parse(diagnostic) => write!(f, "{}", diagnostic),
+            Error::NoSuchFile(path) => write!(f, "{:?} does not exist", path),
+            Error::InvalidPackageName { name, reason } => write!(f, "Invalid package name {:?}: {}", name, reason),
+            Error::InvalidValue { value, reason } => write!(f, "Invalid value {:?}: {}", value, reason),
+            Error::CheckFailed(1) => write!(f, "1 problem found"),
+            Error::CheckFailed(count) => write!(f, "{} problems found", count),
+            Error::Unformatted(paths) => {
+                let paths: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+                write!(f, "Not formatted: {}", paths.join(", "))
+            }
+            Error::WouldChange(paths) => {
+                let paths: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+                write!(f, "Dry run, would change: {}", paths.join(", "))
+            }
+            Error::Git { message, .. } => write!(f, "{}", message),
+            Error::UnknownFlavour { name, suggestion } => {
+                write!(f, "No such flavour {}", name)?;
+                did_you_mean(f, suggestion)
+            }
+            Error::FlavoursFailed(names) => write!(f, "Failed in flavours: {}", names.join(", ")),
+            Error::NoFlavourFiles { dir, files } => {
+                let files: Vec<String> = files.iter().map(|p| p.display().to_string()).collect();
+                write!(f, "No flavour in {:?} has any of {}; set [files] in {} if they are elsewhere",
+                    dir, files.join(", "), crate::config::CONFIG)
+            }
+            Error::SameSource { package, path } => write!(f, "Package {} is already in {:?}", package, path),
+            Error::DuplicateSpec(package) => write!(f, "Package {} given more than once", package),
+            Error::NewFileNotAfterSeparator(path) => {
+                write!(f, "{:?} does not exist; to create it, give it after `--`", path)
+            }
+            Error::NoSpecs => write!(f, "No packages given, expected PACKAGE DEPSPEC, PACKAGE=DEPSPEC or --from-file"),
+            Error::NoFiles(command) => {
+                write!(f, "No FILES given and no default set for `{}` in {}", command, crate::config::CONFIG)
+            }
+            Error::NothingToUndo { available: 0, .. } => write!(f, "Nothing to undo"),
+            Error::NothingToUndo { requested, available } => {
+                write!(f, "Cannot undo {} commands, the journal only has {}", requested, available)
+            }
+            Error::ChangedSince(path) => write!(f, "{:?} has changed since, not undoing", path),
+            Error::AlreadyExists(path) => write!(f, "{:?} already exists", path),
+            Error::NotADirectory(path) => write!(f, "{:?} is not a directory", path),
+            Error::IO { path, error } => write!(f, "{:?}: {}", path, error),
+            Error::RollbackFailed { error, rollback } => {
+                write!(f, "{}, and could not put back the files already written: {}", error, rollback)
+            }
+        }
+    }
+}
+
+fn did_you_mean(f: &mut fmt::Formatter<'_>, suggestion: &Option<String>) -> fmt::Result {
+    match suggestion {
+        Some(name) => write!(f, " (did you mean `{}`?)", name),
+        None => Ok(()),
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::IO { error, .. } => Some(error),
+            Error::RollbackFailed { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// How a line was terminated in the file it came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Eol {
+    Lf,
+    CrLf,
+    /// The last line of a file with no trailing newline.
+    None,
+}
+
+impl Eol {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Eol::Lf => "\n",
+            Eol::CrLf => "\r\n",
+            Eol::None => "",
+        }
+    }
+}
+
+/// One of the three places mess can take a dependency from.
+///
+/// Ordered by precedence: when a package is enabled in several deps
+/// files, mess uses `Path` over `Git` over `Hex`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    Hex,
+    Git,
+    Path,
+}
+
+impl Source {
+    /// All sources, lowest precedence first.
+    pub const ALL: [Source; 3] = [Source::Hex, Source::Git, Source::Path];
+
+    /// The name of the deps file for this source.
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Source::Hex => "deps.hex",
+            Source::Git => "deps.git",
+            Source::Path => "deps.path",
+        }
+    }
+
+    /// The source whose deps file `path` is, judging by its name.
+    pub fn of_file(path: &Path) -> Option<Source> {
+        let name = path.file_name()?;
+        Source::ALL.iter().copied().find(|s| name == s.file_name())
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Source::Hex => "hex",
+            Source::Git => "git",
+            Source::Path => "path",
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Source {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Source, String> {
+        match s {
+            "hex" => Ok(Source::Hex),
+            "git" => Ok(Source::Git),
+            "path" => Ok(Source::Path),
+            _ => Err(format!("unknown source {:?}, expected hex, git or path", s)),
+        }
+    }
+}
+
+/// A reference to check out of a git repository.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GitRef {
+    Branch(String),
+    Tag(String),
+    Commit(String),
+}
+
+/// A typed view of a package's value, according to the deps file it
+/// is in.
+///
+/// In `deps.git`, a value is a URL optionally followed by `#` and a
+/// ref: `#main` is a branch, `#tag:v1.0` a tag and `#commit:abc123` a
+/// commit. Branch names cannot contain `:`, so this is unambiguous.
+///
+/// Formatting a spec gives back exactly the text it was parsed from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DepSpec {
+    Hex { requirement: String },
+    Git { url: String, ref_kind: Option<GitRef> },
+    Path { path: String },
+}
+
+impl DepSpec {
+    /// Reads a value from the deps file for `source`.
+    pub fn parse(source: Source, value: &str) -> DepSpec {
+        match source {
+            Source::Hex => DepSpec::Hex { requirement: value.to_owned() },
+            Source::Path => DepSpec::Path { path: value.to_owned() },
+            Source::Git => {
+                let mut pieces = value.splitn(2, '#');
+                let url = pieces.next().unwrap_or_default().to_owned();
+                let ref_kind = pieces.next().map(|r| {
+                    if let Some(tag) = r.strip_prefix("tag:") {
+                        GitRef::Tag(tag.to_owned())
+                    } else if let Some(sha) = r.strip_prefix("commit:") {
+                        GitRef::Commit(sha.to_owned())
+                    } else {
+                        GitRef::Branch(r.to_owned())
+                    }
+                });
+                DepSpec::Git { url, ref_kind }
+            }
+        }
+    }
+
+    /// Checks the spec makes sense, e.g. that a hex requirement parses.
+    pub fn check(&self) -> Result<(), Error> {
+        match self {
+            DepSpec::Hex { requirement } => requirement.parse::<Requirement>().map(|_| ()).map_err(|e| {
+                Error::InvalidValue { value: requirement.clone(), reason: format!("invalid hex requirement: {}", e) }
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// The deps file this kind of spec belongs in.
+    pub fn source(&self) -> Source {
+        match self {
+            DepSpec::Hex { .. } => Source::Hex,
+            DepSpec::Git { .. } => Source::Git,
+            DepSpec::Path { .. } => Source::Path,
+        }
+    }
+}
+
+impl fmt::Display for DepSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DepSpec::Hex { requirement } => write!(f, "{}", requirement),
+            DepSpec::Path { path } => write!(f, "{}", path),
+            DepSpec::Git { url, ref_kind } => {
+                write!(f, "{}", url)?;
+                match ref_kind {
+                    None => Ok(()),
+                    Some(GitRef::Branch(branch)) => write!(f, "#{}", branch),
+                    Some(GitRef::Tag(tag)) => write!(f, "#tag:{}", tag),
+                    Some(GitRef::Commit(sha)) => write!(f, "#commit:{}", sha),
+                }
+            }
+        }
+    }
+}
+
+/// Checks that a value can be written to a deps file and read back.
+pub fn check_value(value: &str) -> Result<(), Error> {
+    let reason = if value.is_empty() {
+        "values may not be empty"
+    } else if value.contains(['\n', '\r']) {
+        "values may not contain line breaks"
+    } else {
+        return Ok(());
+    };
+    Err(Error::InvalidValue { value: value.to_owned(), reason: reason.to_owned() })
+}
+
+/// The quotes around a value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quote {
+    Double,
+    Single,
+}
+
+impl Quote {
+    pub fn char(self) -> char {
+        match self {
+            Quote::Double => '"',
+            Quote::Single => '\'',
+        }
+    }
+
+    /// Writes a value between these quotes, escaping backslashes and
+    /// the quote character.
+    pub fn quote(self, value: &str) -> String {
+        let quote = self.char();
+        let mut out = String::with_capacity(value.len() + 2);
+        out.push(quote);
+        for c in value.chars() {
+            if c == '\\' || c == quote {
+                out.push('\\');
+            }
+            out.push(c);
+        }
+        out.push(quote);
+        out
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct Package {
     pub name: String,
+    /// Everything between the name and the opening quote, e.g. `" = "`.
+    pub eq: String,
+    pub quote: Quote,
+    /// The value with any escapes removed.
     pub version: String,
 }
 
 impl Package {
     pub fn new(name: &str, version: &str) -> Package {
-        Package { name: name.to_owned(), version: version.to_owned() }
+        Package {
+            name: name.to_owned(), eq: " = ".to_owned(),
+            quote: Quote::Double, version: version.to_owned(),
+        }
     }
 
     pub fn update(&mut self, version: &str, file: &Path) {
@@ -40,33 +457,82 @@ impl Package {
             self.version = version.to_string();
         }
     }
+
+    /// The value read as a spec for the deps file for `source`.
+    pub fn spec(&self, source: Source) -> DepSpec {
+        DepSpec::parse(source, &self.version)
+    }
+
+    /// Replaces the value with a formatted spec.
+    pub fn set_spec(&mut self, spec: &DepSpec, file: &Path) {
+        self.update(&spec.to_string(), file)
+    }
+
+    /// The version as it is written in the file, quoted and escaped.
+    pub fn value_literal(&self) -> String {
+        self.quote.quote(&self.version)
+    }
+
+    /// Where each token falls on a line where the package starts at
+    /// byte `start` and is followed by `post`.
+    fn spans(&self, start: usize, post: &str) -> Spans {
+        let name = start..start + self.name.len();
+        let eq_at = name.end + self.eq.find('=').unwrap_or(0);
+        let value_at = name.end + self.eq.len();
+        let value = value_at..value_at + self.value_literal().len();
+        let post = value.end..value.end + post.len();
+        Spans { name, eq: eq_at..eq_at + 1, value, post }
+    }
 }
 
 impl fmt::Display for Package {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{} = \"{}\"", &self.name, &self.version)
+        write!(f, "{}{}{}", &self.name, &self.eq, self.value_literal())
     }
 }
 
+/// Byte ranges of the tokens of a dependency line, relative to the
+/// start of the line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Spans {
+    pub name: Range<usize>,
+    /// The `=` itself, without the surrounding spaces.
+    pub eq: Range<usize>,
+    /// The quoted value, quotes included.
+    pub value: Range<usize>,
+    /// Whatever follows the value: trailing spaces and any comment.
+    pub post: Range<usize>,
+}
+
 #[derive(Clone, Debug)]
 pub struct Enabled {
+    pub number: usize,
     pub pre: String,
     pub package: Package,
     pub post: String,
+    pub eol: Eol,
 }
 
 impl Enabled {
     pub fn new(pre: &str, post: &str, package: Package) -> Enabled {
-        Enabled { pre: pre.to_owned(), post: post.to_owned(), package }
+        Enabled { number: 0, pre: pre.to_owned(), post: post.to_owned(), package, eol: Eol::Lf }
     }
 
-    pub fn disable(self) -> Disabled {
-        Disabled { pre: "# ".to_string(), package: self.package, post: self.post }
+    /// Comments the line out with `marker`, e.g. `"# "`.
+    pub fn disable(self, marker: &str) -> Disabled {
+        Disabled {
+            number: self.number, pre: marker.to_string(), package: self.package,
+            post: self.post, eol: self.eol,
+        }
     }
 
     pub fn update(&mut self, version: &str, file: &Path) {
         self.package.update(version, file)
     }
+
+    pub fn spans(&self) -> Spans {
+        self.package.spans(self.pre.len(), &self.post)
+    }
 }
 
 impl fmt::Display for Enabled {
@@ -77,23 +543,33 @@ impl fmt::Display for Enabled {
 
 #[derive(Clone, Debug)]
 pub struct Disabled {
+    pub number: usize,
+    /// Indentation and comment marker, e.g. `"# "`.
     pub pre: String,
     pub package: Package,
     pub post: String,
+    pub eol: Eol,
 }
 
 impl Disabled {
     pub fn new(pre: String, post: &str, package: Package) -> Disabled {
-        Disabled { pre, post: post.to_owned(), package }
+        Disabled { number: 0, pre, post: post.to_owned(), package, eol: Eol::Lf }
     }
 
     pub fn enable(self) -> Enabled {
-        Enabled { pre: "".to_string(), package: self.package, post: self.post }
+        Enabled {
+            number: self.number, pre: "".to_string(), package: self.package,
+            post: self.post, eol: self.eol,
+        }
     }
 
     pub fn update(&mut self, version: &str, file: &Path) {
         self.package.update(version, file)
     }
+
+    pub fn spans(&self) -> Spans {
+        self.package.spans(self.pre.len(), &self.post)
+    }
 }
 
 impl fmt::Display for Disabled {
@@ -102,14 +578,104 @@ impl fmt::Display for Disabled {
     }
 }
 
+/// A line containing nothing but whitespace.
+#[derive(Clone, Debug)]
+pub struct Blank {
+    pub number: usize,
+    pub text: String,
+    pub eol: Eol,
+}
+
+/// A comment that is not a disabled dependency.
+#[derive(Clone, Debug)]
+pub struct Comment {
+    pub number: usize,
+    /// Indentation and comment marker, e.g. `"## "`.
+    pub marker: String,
+    pub text: String,
+    pub eol: Eol,
+}
+
+/// A line that is neither a dependency, a comment nor blank.
+#[derive(Clone, Debug)]
+pub struct Malformed {
+    pub number: usize,
+    pub text: String,
+    /// Byte offset into `text` where parsing gave up.
+    pub column: usize,
+    pub message: String,
+    pub eol: Eol,
+}
+
 #[derive(Clone, Debug)]
 pub enum Line {
     Enabled(Enabled),
     Disabled(Disabled),
-    Ignored(String),
+    Blank(Blank),
+    Comment(Comment),
+    Malformed(Malformed),
 }
 
 impl Line {
+    /// The line's number in the file it was parsed from, starting at 1.
+    pub fn number(&self) -> usize {
+        match self {
+            Line::Enabled(e) => e.number,
+            Line::Disabled(d) => d.number,
+            Line::Blank(b) => b.number,
+            Line::Comment(c) => c.number,
+            Line::Malformed(m) => m.number,
+        }
+    }
+
+    pub fn set_number(&mut self, number: usize) {
+        match self {
+            Line::Enabled(e) => e.number = number,
+            Line::Disabled(d) => d.number = number,
+            Line::Blank(b) => b.number = number,
+            Line::Comment(c) => c.number = number,
+            Line::Malformed(m) => m.number = number,
+        }
+    }
+
+    pub fn eol(&self) -> Eol {
+        match self {
+            Line::Enabled(e) => e.eol,
+            Line::Disabled(d) => d.eol,
+            Line::Blank(b) => b.eol,
+            Line::Comment(c) => c.eol,
+            Line::Malformed(m) => m.eol,
+        }
+    }
+
+    pub fn set_eol(&mut self, eol: Eol) {
+        match self {
+            Line::Enabled(e) => e.eol = eol,
+            Line::Disabled(d) => d.eol = eol,
+            Line::Blank(b) => b.eol = eol,
+            Line::Comment(c) => c.eol = eol,
+            Line::Malformed(m) => m.eol = eol,
+        }
+    }
+
+    /// The package on this line, whether enabled or disabled.
+    pub fn package(&self) -> Option<&Package> {
+        match self {
+            Line::Enabled(e) => Some(&e.package),
+            Line::Disabled(d) => Some(&d.package),
+            _ => None,
+        }
+    }
+
+    /// Token spans, for lines with a package on them.
+    pub fn spans(&self) -> Option<Spans> {
+        match self {
+            Line::Enabled(e) => Some(e.spans()),
+            Line::Disabled(d) => Some(d.spans()),
+            _ => None,
+        }
+    }
+
     pub fn enable(self, package: &str, file: &Path) -> Line {
         match self {
             Line::Disabled(d) => {
@@ -121,11 +687,11 @@ impl Line {
         }
     }
 
-    pub fn disable(self, package: &str, file: &Path) -> Line {
+    pub fn disable(self, package: &str, marker: &str, file: &Path) -> Line {
         match self {
             Line::Enabled(e) => {
                 println!("Disabling  package {} at version {} in file {:?}", package, e.package.version, file);
-                Line::Disabled(e.disable())
+                Line::Disabled(e.disable(marker))
             }
             Line::Disabled(_) => self,
             _ => unreachable!()
@@ -146,7 +712,87 @@ impl fmt::Display for Line {
         match self {
             Line::Enabled(a) => write!(f, "{}", a),
             Line::Disabled(i) => write!(f, "{}", i),
-            Line::Ignored(i) => write!(f, "{}", i),
+            Line::Blank(b) => write!(f, "{}", b.text),
+            Line::Comment(c) => write!(f, "{}{}", c.marker, c.text),
+            Line::Malformed(m) => write!(f, "{}", m.text),
+        }
+    }
+}
+
+/// The lines of a deps file along with the layout needed to write it
+/// back byte for byte: a leading BOM and each line's terminator.
+#[derive(Clone, Debug, Default)]
+pub struct Document {
+    pub bom: bool,
+    pub lines: Vec<Line>,
+}
+
+impl Document {
+    pub fn new(bom: bool, lines: Vec<Line>) -> Document {
+        Document { bom, lines }
+    }
+
+    /// The first malformed line, if any.
+    pub fn malformed(&self) -> Option<&Malformed> {
+        self.lines.iter().find_map(|line| match line {
+            Line::Malformed(m) => Some(m),
+            _ => None,
+        })
+    }
+
+    /// The terminator most lines in the file use.
+    pub fn eol(&self) -> Eol {
+        let crlf = self.lines.iter().filter(|l| l.eol() == Eol::CrLf).count();
+        let lf = self.lines.iter().filter(|l| l.eol() == Eol::Lf).count();
+        if crlf > lf { Eol::CrLf } else { Eol::Lf }
+    }
+
+    /// Appends a line, using the file's line endings and keeping a
+    /// missing trailing newline missing. It is numbered as if it
+    /// followed the current last line in the file.
+    pub fn push(&mut self, mut line: Line) {
+        let eol = self.eol();
+        line.set_number(self.lines.last().map(|l| l.number()).unwrap_or(0) + 1);
+        match self.lines.last_mut() {
+            Some(last) if last.eol() == Eol::None => {
+                last.set_eol(eol);
+                line.set_eol(Eol::None);
+            }
+            _ => line.set_eol(eol),
+        }
+        self.lines.push(line);
+    }
+
+    /// Removes a line. If it was the last one, the new last line takes
+    /// over its terminator.
+    pub fn remove(&mut self, index: usize) -> Line {
+        let line = self.lines.remove(index);
+        if index == self.lines.len() {
+            if let Some(last) = self.lines.last_mut() {
+                last.set_eol(line.eol());
+            }
+        }
+        line
+    }
+}
+
+impl Deref for Document {
+    type Target = Vec<Line>;
+    fn deref(&self) -> &Vec<Line> { &self.lines }
+}
+
+impl DerefMut for Document {
+    fn deref_mut(&mut self) -> &mut Vec<Line> { &mut self.lines }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.bom {
+            write!(f, "\u{feff}")?;
+        }
+        for line in &self.lines {
+            write!(f, "{}{}", line, line.eol().as_str())?;
         }
+        Ok(())
     }
 }
@@ -0,0 +1,357 @@
+//! Versions and version requirements as Hex and Elixir's `Version`
+//! module understand them, e.g. `~> 0.9 and >= 0.9.2`.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// One dot-separated part of a pre-release tag.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Identifier {
+    Numeric(u64),
+    Alpha(String),
+}
+
+impl Ord for Identifier {
+    fn cmp(&self, other: &Identifier) -> Ordering {
+        use Identifier::*;
+        match (self, other) {
+            (Numeric(a), Numeric(b)) => a.cmp(b),
+            (Numeric(_), Alpha(_)) => Ordering::Less,
+            (Alpha(_), Numeric(_)) => Ordering::Greater,
+            (Alpha(a), Alpha(b)) => a.cmp(b),
+        }
+    }
+}
+
+impl PartialOrd for Identifier {
+    fn partial_cmp(&self, other: &Identifier) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Identifier::Numeric(n) => write!(f, "{}", n),
+            Identifier::Alpha(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// A semantic version, e.g. `1.2.3-rc.1+build.5`.
+///
+/// Build metadata is kept but, as semver requires, ignored when
+/// comparing versions.
+#[derive(Clone, Debug)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    pub pre: Vec<Identifier>,
+    pub build: Option<String>,
+}
+
+impl Version {
+    pub fn new(major: u64, minor: u64, patch: u64) -> Version {
+        Version { major, minor, patch, pre: Vec::new(), build: None }
+    }
+
+    pub fn is_pre(&self) -> bool {
+        !self.pre.is_empty()
+    }
+
+    /// Parses a version that may leave off its patch number, as the
+    /// operand of `~>` may. Returns whether the patch was given.
+    fn parse_partial(s: &str) -> Result<(Version, bool), String> {
+        let (s, build) = match s.split_once('+') {
+            Some((s, build)) if is_identifiers(build) => (s, Some(build.to_owned())),
+            Some(_) => return Err(format!("invalid build metadata in version {:?}", s)),
+            None => (s, None),
+        };
+        let (s, pre) = match s.split_once('-') {
+            Some((s, pre)) => (s, parse_pre(pre).ok_or_else(|| format!("invalid pre-release in version {:?}", s))?),
+            None => (s, Vec::new()),
+        };
+        let parts = s.split('.').map(parse_number).collect::<Option<Vec<u64>>>()
+            .ok_or_else(|| format!("invalid version {:?}", s))?;
+        match parts[..] {
+            [major, minor, patch] => Ok((Version { major, minor, patch, pre, build }, true)),
+            [major, minor] => Ok((Version { major, minor, patch: 0, pre, build }, false)),
+            _ => Err(format!("invalid version {:?}, expected MAJOR.MINOR.PATCH", s)),
+        }
+    }
+}
+
+fn parse_number(s: &str) -> Option<u64> {
+    let leading_zero = s.len() > 1 && s.starts_with('0');
+    if s.is_empty() || leading_zero || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+fn is_identifiers(s: &str) -> bool {
+    s.split('.').all(|i| !i.is_empty() && i.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-'))
+}
+
+fn parse_pre(s: &str) -> Option<Vec<Identifier>> {
+    if !is_identifiers(s) {
+        return None;
+    }
+    s.split('.').map(|i| {
+        if i.bytes().all(|b| b.is_ascii_digit()) {
+            parse_number(i).map(Identifier::Numeric)
+        } else {
+            Some(Identifier::Alpha(i.to_owned()))
+        }
+    }).collect()
+}
+
+impl FromStr for Version {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Version, String> {
+        match Version::parse_partial(s)? {
+            (version, true) => Ok(version),
+            (_, false) => Err(format!("invalid version {:?}, expected MAJOR.MINOR.PATCH", s)),
+        }
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Version) -> Ordering {
+        (self.major, self.minor, self.patch).cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (self.is_pre(), other.is_pre()) {
+                // a pre-release comes before the release itself
+                (false, false) => Ordering::Equal,
+                (true, false) => Ordering::Less,
+                (false, true) => Ordering::Greater,
+                (true, true) => self.pre.cmp(&other.pre),
+            })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Version) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Version) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if self.is_pre() {
+            let pre: Vec<String> = self.pre.iter().map(|i| i.to_string()).collect();
+            write!(f, "-{}", pre.join("."))?;
+        }
+        if let Some(build) = &self.build {
+            write!(f, "+{}", build)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    /// `~>`: at least this version, below the next major (or minor,
+    /// if a patch number is given).
+    Compatible,
+}
+
+impl Op {
+    fn as_str(self) -> &'static str {
+        match self {
+            Op::Eq => "==",
+            Op::Ne => "!=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Compatible => "~>",
+        }
+    }
+}
+
+/// A single comparison, e.g. `>= 0.9.2`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Constraint {
+    pub op: Op,
+    pub version: Version,
+    /// Whether the patch number was given, which changes what `~>`
+    /// allows.
+    pub has_patch: bool,
+}
+
+impl Constraint {
+    pub fn matches(&self, version: &Version) -> bool {
+        let v = &self.version;
+        match self.op {
+            Op::Eq => version == v,
+            Op::Ne => version != v,
+            Op::Gt => version > v,
+            Op::Ge => version >= v,
+            Op::Lt => version < v,
+            Op::Le => version <= v,
+            Op::Compatible => {
+                let upper = if self.has_patch {
+                    Version::new(v.major, v.minor + 1, 0)
+                } else {
+                    Version::new(v.major + 1, 0, 0)
+                };
+                // pre-releases of the upper bound are out of range too
+                let below_upper = (version.major, version.minor, version.patch)
+                    < (upper.major, upper.minor, upper.patch);
+                version >= v && below_upper
+            }
+        }
+    }
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.has_patch {
+            write!(f, "{} {}", self.op.as_str(), self.version)
+        } else {
+            write!(f, "{} {}.{}", self.op.as_str(), self.version.major, self.version.minor)
+        }
+    }
+}
+
+/// A version requirement: constraints joined with `and` and `or`,
+/// where `and` binds tighter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Requirement {
+    /// Alternatives joined with `or`, each a list of constraints
+    /// joined with `and`.
+    pub alternatives: Vec<Vec<Constraint>>,
+}
+
+impl Requirement {
+    pub fn matches(&self, version: &Version) -> bool {
+        self.alternatives.iter().any(|all| all.iter().all(|c| c.matches(version)))
+    }
+}
+
+const OPS: [(&str, Op); 7] = [
+    ("~>", Op::Compatible),
+    ("==", Op::Eq),
+    ("!=", Op::Ne),
+    (">=", Op::Ge),
+    ("<=", Op::Le),
+    (">", Op::Gt),
+    ("<", Op::Lt),
+];
+
+/// Reads one constraint from the front of `tokens`.
+fn parse_constraint<'a, I>(tokens: &mut std::iter::Peekable<I>) -> Result<Constraint, String>
+where I: Iterator<Item = &'a str> {
+    let token = tokens.next().ok_or("expected a version")?;
+    let (op, rest) = OPS.iter()
+        .find_map(|(text, op)| token.strip_prefix(text).map(|rest| (*op, rest)))
+        .unwrap_or((Op::Eq, token));
+    let version = if rest.is_empty() {
+        tokens.next().ok_or_else(|| format!("expected a version after `{}`", op.as_str()))?
+    } else {
+        rest
+    };
+    let (version, has_patch) = Version::parse_partial(version)?;
+    if !has_patch && op != Op::Compatible {
+        return Err(format!("only `~>` accepts a version without a patch number, got `{} {}.{}`",
+                           op.as_str(), version.major, version.minor));
+    }
+    Ok(Constraint { op, version, has_patch })
+}
+
+impl FromStr for Requirement {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Requirement, String> {
+        let mut tokens = s.split_whitespace().peekable();
+        let mut alternatives = vec![vec![parse_constraint(&mut tokens)?]];
+        while let Some(joiner) = tokens.next() {
+            match joiner {
+                "and" => alternatives.last_mut().unwrap().push(parse_constraint(&mut tokens)?),
+                "or" => alternatives.push(vec![parse_constraint(&mut tokens)?]),
+                other => return Err(format!("expected `and` or `or`, got `{}`", other)),
+            }
+        }
+        Ok(Requirement { alternatives })
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let alternatives: Vec<String> = self.alternatives.iter().map(|all| {
+            all.iter().map(|c| c.to_string()).collect::<Vec<String>>().join(" and ")
+        }).collect();
+        write!(f, "{}", alternatives.join(" or "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(requirement: &str, version: &str) -> bool {
+        let requirement: Requirement = requirement.parse().unwrap();
+        requirement.matches(&version.parse().unwrap())
+    }
+
+    #[test]
+    fn compatible_without_patch_allows_minor_bumps() {
+        assert!(matches("~> 1.0", "1.0.0"));
+        assert!(matches("~> 1.0", "1.9.9"));
+        assert!(!matches("~> 1.0", "2.0.0"));
+        assert!(!matches("~> 1.0", "0.9.9"));
+    }
+
+    #[test]
+    fn compatible_with_patch_allows_patch_bumps() {
+        assert!(matches("~> 1.2.3", "1.2.3"));
+        assert!(matches("~> 1.2.3", "1.2.9"));
+        assert!(!matches("~> 1.2.3", "1.3.0"));
+        assert!(!matches("~> 1.2.3", "1.2.2"));
+    }
+
+    #[test]
+    fn compatible_excludes_pre_releases_of_upper_bound() {
+        assert!(!matches("~> 1.0", "2.0.0-rc.1"));
+        assert!(!matches("~> 1.2.3", "1.3.0-rc.1"));
+        assert!(matches("~> 1.0", "1.5.0-rc.1"));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let requirement = "< 1.0.0 or >= 2.0.0 and < 3.0.0";
+        assert_eq!(requirement.parse::<Requirement>().unwrap().alternatives.len(), 2);
+        assert!(matches(requirement, "0.5.0"));
+        assert!(matches(requirement, "2.5.0"));
+        assert!(!matches(requirement, "1.5.0"));
+        assert!(!matches(requirement, "3.0.0"));
+    }
+
+    #[test]
+    fn rejects_partial_version_without_compatible() {
+        assert!("== 1.0".parse::<Requirement>().is_err());
+        assert!(">= 1.0".parse::<Requirement>().is_err());
+    }
+
+    #[test]
+    fn rejects_trailing_joiner() {
+        assert!(">= 1.0.0 and".parse::<Requirement>().is_err());
+        assert!(">= 1.0.0 or".parse::<Requirement>().is_err());
+    }
+}